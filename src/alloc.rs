@@ -0,0 +1,258 @@
+//! Leak checking for raw allocations.
+//!
+//! Tokens check that a container drops its elements; `CheckedAlloc` checks that it frees the
+//! memory it manages itself. Install it as the global allocator of a test binary, then run the
+//! code under test with `CheckedAlloc::check` or `CheckedAlloc::assert_no_leaks`:
+//!
+//! ```
+//! use dropcheck::alloc::CheckedAlloc;
+//! use std::alloc::System;
+//!
+//! #[global_allocator]
+//! static ALLOC: CheckedAlloc = CheckedAlloc::new(System);
+//!
+//! # fn main() {
+//! let (_, leaks) = ALLOC.check(|| {
+//!     drop(vec![1u8; 100]);
+//!     Box::leak(Box::new([0u8; 32]));
+//! });
+//! assert_eq!(leaks.len(), 1);
+//! assert_eq!(leaks[0].size(), 32);
+//! # }
+//! ```
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::cell::Cell;
+use std::fmt;
+use std::sync::{Mutex, PoisonError, atomic::{AtomicU64, AtomicUsize, Ordering}};
+use std::thread;
+
+thread_local! {
+    /// The scope allocations made on this thread are recorded in, or zero for none.
+    static SCOPE: Cell<u64> = const { Cell::new(0) };
+
+    /// Set while the allocator itself is running, so its own allocations aren't recorded.
+    static IN_ALLOC: Cell<bool> = const { Cell::new(false) };
+}
+
+/// An allocation made within a checked scope that wasn't freed by the end of it.
+pub struct Allocation {
+    scope: u64,
+    ptr: usize,
+    layout: Layout,
+    backtrace: Backtrace,
+}
+
+impl Allocation {
+    /// Returns the size of the allocation, in bytes.
+    pub fn size(&self) -> usize {
+        self.layout.size()
+    }
+
+    /// Returns the alignment of the allocation.
+    pub fn align(&self) -> usize {
+        self.layout.align()
+    }
+
+    /// Returns where the allocation was made.
+    ///
+    /// Backtraces are only captured if enabled with the `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE`
+    /// environment variables; see `Backtrace::capture`.
+    pub fn backtrace(&self) -> &Backtrace {
+        &self.backtrace
+    }
+}
+
+impl fmt::Debug for Allocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Allocation")
+         .field("ptr", &(self.ptr as *const u8))
+         .field("size", &self.size())
+         .field("align", &self.align())
+         .finish()
+    }
+}
+
+impl fmt::Display for Allocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} bytes at {:p} (align {})", self.size(), self.ptr as *const u8, self.align())
+    }
+}
+
+/// A global allocator that records the allocations made within checked scopes.
+///
+/// Wraps another allocator, `System` by default, which does the actual allocating. Outside of
+/// checked scopes the only overhead is an atomic load per allocation.
+#[derive(Debug)]
+pub struct CheckedAlloc<A = System> {
+    inner: A,
+    active: AtomicUsize,
+    next_scope: AtomicU64,
+    live: Mutex<Vec<Allocation>>,
+}
+
+impl<A> CheckedAlloc<A> {
+    /// Wraps `inner`.
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner,
+            active: AtomicUsize::new(0),
+            next_scope: AtomicU64::new(1),
+            live: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Runs `f`, returning its result and every allocation it made on the current thread that
+    /// wasn't freed by the time it returned.
+    ///
+    /// Allocations made on other threads aren't recorded, but freeing a recorded allocation on
+    /// another thread is. Has no effect unless this allocator is the global allocator.
+    ///
+    /// If `f` panics, the allocations it made are discarded as the panic unwinds, and the thread
+    /// goes back to recording into the enclosing scope, if any:
+    ///
+    /// ```
+    /// use dropcheck::alloc::CheckedAlloc;
+    /// use std::alloc::System;
+    /// use std::panic;
+    ///
+    /// #[global_allocator]
+    /// static ALLOC: CheckedAlloc = CheckedAlloc::new(System);
+    ///
+    /// # fn main() {
+    /// let (_, leaks) = ALLOC.check(|| {
+    ///     let result = panic::catch_unwind(|| ALLOC.check(|| panic!("oops")));
+    ///     assert!(result.is_err());
+    ///
+    ///     Box::leak(Box::new([0u8; 32]));
+    /// });
+    /// assert_eq!(leaks.len(), 1);
+    /// # }
+    /// ```
+    pub fn check<F, R>(&self, f: F) -> (R, Vec<Allocation>)
+        where F: FnOnce() -> R
+    {
+        let scope = Scope::enter(self);
+        let result = f();
+        (result, scope.leaks())
+    }
+
+    /// Runs `f`, asserting that every allocation it made on the current thread was freed by the
+    /// time it returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics with the size of every leaked allocation, and where it was made if backtraces are
+    /// enabled.
+    #[track_caller]
+    pub fn assert_no_leaks<F, R>(&self, f: F) -> R
+        where F: FnOnce() -> R
+    {
+        let (result, leaks) = self.check(f);
+        if !leaks.is_empty() {
+            let mut msg = String::from("allocations not freed:");
+            for leak in &leaks {
+                msg.push_str(&format!("\n    {}", leak));
+                if let BacktraceStatus::Captured = leak.backtrace.status() {
+                    msg.push_str(&format!("\n{}", leak.backtrace));
+                }
+            }
+            panic!("{}", msg);
+        }
+        result
+    }
+
+    fn record(&self, ptr: *mut u8, layout: Layout) {
+        // Capturing a backtrace while a panic is printing one would deadlock.
+        let scope = SCOPE.try_with(Cell::get).unwrap_or(0);
+        if scope != 0 && !ptr.is_null() && !thread::panicking() {
+            without_recording(|| {
+                let backtrace = Backtrace::capture();
+                self.live.lock().unwrap_or_else(PoisonError::into_inner)
+                    .push(Allocation { scope, ptr: ptr as usize, layout, backtrace });
+            });
+        }
+    }
+
+    fn forget(&self, ptr: *mut u8) {
+        without_recording(|| {
+            let mut live = self.live.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Some(i) = live.iter().position(|allocation| allocation.ptr == ptr as usize) {
+                live.swap_remove(i);
+            }
+        });
+    }
+
+    fn is_recording(&self) -> bool {
+        self.active.load(Ordering::SeqCst) > 0 && !IN_ALLOC.try_with(Cell::get).unwrap_or(true)
+    }
+}
+
+/// A checked scope on the current thread, which is left when dropped, even by a panic.
+struct Scope<'a, A> {
+    alloc: &'a CheckedAlloc<A>,
+    id: u64,
+    outer: u64,
+}
+
+impl<'a, A> Scope<'a, A> {
+    fn enter(alloc: &'a CheckedAlloc<A>) -> Self {
+        let id = alloc.next_scope.fetch_add(1, Ordering::SeqCst);
+        let outer = SCOPE.with(|current| current.replace(id));
+        alloc.active.fetch_add(1, Ordering::SeqCst);
+        Self { alloc, id, outer }
+    }
+
+    /// Stops recording into the scope, returning the allocations recorded in it.
+    fn leaks(&self) -> Vec<Allocation> {
+        SCOPE.with(|current| current.set(self.outer));
+        without_recording(|| {
+            let mut live = self.alloc.live.lock().unwrap_or_else(PoisonError::into_inner);
+            let (leaks, others) = live.drain(..)
+                                      .partition(|allocation| allocation.scope == self.id);
+            *live = others;
+            leaks
+        })
+    }
+}
+
+impl<A> Drop for Scope<'_, A> {
+    fn drop(&mut self) {
+        let leaks = self.leaks();
+        without_recording(|| drop(leaks));
+        self.alloc.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Runs `f` with recording disabled on the current thread, so the allocator's own bookkeeping
+/// isn't recorded, and doesn't deadlock on `live`.
+fn without_recording<F, R>(f: F) -> R
+    where F: FnOnce() -> R
+{
+    IN_ALLOC.with(|in_alloc| in_alloc.set(true));
+    let result = f();
+    IN_ALLOC.with(|in_alloc| in_alloc.set(false));
+    result
+}
+
+// SAFETY: every allocation is made and freed by the inner allocator, unchanged; this only keeps
+// track of the pointers.
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CheckedAlloc<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // SAFETY: forwarded from the caller.
+        let ptr = unsafe { self.inner.alloc(layout) };
+        if self.is_recording() {
+            self.record(ptr, layout);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if self.is_recording() {
+            self.forget(ptr);
+        }
+        // SAFETY: forwarded from the caller.
+        unsafe { self.inner.dealloc(ptr, layout) }
+    }
+}
@@ -0,0 +1,95 @@
+//! Tokens that borrow data, for testing the soundness of `#[may_dangle]`.
+
+use std::marker::PhantomData;
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+
+use super::DropToken;
+
+/// Data for `BorrowToken`s to borrow, which records when it's dropped.
+#[derive(Debug)]
+pub struct BorrowTarget {
+    alive: Arc<AtomicBool>,
+}
+
+impl BorrowTarget {
+    /// Creates a new target.
+    pub fn new() -> Self {
+        Self { alive: Arc::new(AtomicBool::new(true)) }
+    }
+
+    /// Returns true if the target hasn't been dropped yet.
+    pub fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::SeqCst)
+    }
+}
+
+impl Default for BorrowTarget {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for BorrowTarget {
+    fn drop(&mut self) {
+        self.alive.store(false, Ordering::SeqCst);
+    }
+}
+
+/// A `DropToken` that borrows a `BorrowTarget`, and checks that the target is still alive when
+/// dropped.
+///
+/// Created by `DropCheck::borrow_token`. Since the token has a `Drop` implementation and holds the
+/// borrow, the compiler's drop check requires the target to outlive the token. A container that
+/// opts out of that requirement with `#[may_dangle]`, but then drops its elements anyway, lets the
+/// target be dropped first; the token then panics when it's dropped.
+///
+/// The token shares the target's liveness flag rather than reading it through the borrow, so the
+/// check itself never touches the dangling borrow.
+///
+/// # Examples
+///
+/// `Vec` uses `#[may_dangle]` soundly, so the compiler still rejects dropping the target first:
+///
+/// ```compile_fail
+/// # use dropcheck::{BorrowTarget, DropCheck};
+/// let dropcheck = DropCheck::new();
+/// let v;
+/// let target = BorrowTarget::new();
+/// v = vec![dropcheck.borrow_token(&target)];
+/// // error: `target` does not live long enough
+/// ```
+///
+/// Whereas it's fine for the target to outlive the vector:
+///
+/// ```
+/// # use dropcheck::{BorrowTarget, DropCheck};
+/// let dropcheck = DropCheck::new();
+/// let target = BorrowTarget::new();
+/// let v = vec![dropcheck.borrow_token(&target), dropcheck.borrow_token(&target)];
+/// drop(v);
+/// ```
+#[derive(Debug)]
+pub struct BorrowToken<'a> {
+    token: DropToken,
+    alive: Arc<AtomicBool>,
+    target: PhantomData<&'a BorrowTarget>,
+}
+
+impl<'a> BorrowToken<'a> {
+    pub(crate) fn new(token: DropToken, target: &'a BorrowTarget) -> Self {
+        Self { token, alive: Arc::clone(&target.alive), target: PhantomData }
+    }
+
+    /// Returns the id of this token's state.
+    pub fn id(&self) -> u64 {
+        self.token.id()
+    }
+}
+
+impl Drop for BorrowToken<'_> {
+    fn drop(&mut self) {
+        if !self.alive.load(Ordering::SeqCst) {
+            panic!("{} dropped after the data it borrows", *self.token.state);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Creating tokens with any combination of options.
+
+use std::sync::{Arc, PoisonError, atomic::{AtomicBool, Ordering}};
+
+use super::{DropCheck, DropToken, Generation};
+
+/// Builds a `DropToken` with any combination of the options of the `DropCheck` constructors.
+///
+/// Created by `DropCheck::build_token`. Options that aren't set default to those of
+/// `DropCheck::token`.
+///
+/// # Examples
+///
+/// ```
+/// # use dropcheck::DropCheck;
+/// let dropcheck = DropCheck::new();
+///
+/// let token = dropcheck.build_token()
+///     .id(7)
+///     .name("head")
+///     .panic_on_drop()
+///     .value([0u8; 64])
+///     .finish();
+/// assert_eq!(token.len(), 64);
+///
+/// assert!(std::panic::catch_unwind(move || drop(token)).is_err());
+/// assert_eq!(dropcheck.report().tokens()[0].to_string(), "token #7 (head)");
+/// ```
+#[derive(Debug)]
+#[must_use = "the token is only created by `finish`"]
+pub struct TokenBuilder<'a, T = ()> {
+    dropcheck: &'a DropCheck,
+    id: Option<u64>,
+    name: Option<String>,
+    panic_on_drop: bool,
+    expected_drops: usize,
+    barrier: Option<Arc<AtomicBool>>,
+    generation: Option<Generation>,
+    value: T,
+}
+
+impl<'a> TokenBuilder<'a> {
+    pub(crate) fn new(dropcheck: &'a DropCheck) -> Self {
+        Self {
+            dropcheck,
+            id: None,
+            name: None,
+            panic_on_drop: false,
+            expected_drops: 1,
+            barrier: None,
+            generation: None,
+            value: (),
+        }
+    }
+}
+
+impl<'a, T> TokenBuilder<'a, T> {
+    /// Chooses the token's id, as with `DropCheck::token_with_id`.
+    pub fn id(mut self, id: u64) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    /// Names the token, so reports show the name along with its id.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_owned());
+        self
+    }
+
+    /// Makes the token panic when it's dropped, as with `DropCheck::panicking_token`.
+    pub fn panic_on_drop(mut self) -> Self {
+        self.panic_on_drop = true;
+        self
+    }
+
+    /// Expects the token to be dropped exactly `count` times, rather than once.
+    ///
+    /// For containers built with `ManuallyDrop` or unions that deliberately run a value's drop
+    /// glue along several paths. Only the last expected drop drops the payload; the set's check
+    /// fails if the token was dropped fewer or more times than expected.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `count` is zero; leak the token with `DropToken::leak_intentionally` instead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// use std::mem::ManuallyDrop;
+    ///
+    /// let dropcheck = DropCheck::new();
+    /// let mut token = ManuallyDrop::new(dropcheck.build_token().expected_drops(2).finish());
+    ///
+    /// unsafe { ManuallyDrop::drop(&mut token) };
+    /// assert!(!dropcheck.all_dropped());
+    /// unsafe { ManuallyDrop::drop(&mut token) };
+    /// assert!(dropcheck.all_dropped());
+    /// ```
+    ///
+    /// The payload stays usable until the last expected drop:
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// use std::mem::ManuallyDrop;
+    ///
+    /// let dropcheck = DropCheck::new();
+    /// let (payload, payload_state) = dropcheck.pair();
+    /// let mut token = ManuallyDrop::new(dropcheck.build_token()
+    ///                                            .expected_drops(3)
+    ///                                            .value(payload)
+    ///                                            .finish());
+    ///
+    /// for _ in 0 .. 2 {
+    ///     unsafe { ManuallyDrop::drop(&mut token) };
+    ///     assert!(payload_state.is_not_dropped());
+    /// }
+    /// unsafe { ManuallyDrop::drop(&mut token) };
+    /// assert!(payload_state.is_dropped());
+    /// dropcheck.check().unwrap();
+    /// ```
+    #[track_caller]
+    pub fn expected_drops(mut self, count: usize) -> Self {
+        assert!(count > 0, "a token must be expected to be dropped at least once");
+        self.expected_drops = count;
+        self
+    }
+
+    /// Makes dropping the token before `passed` is set a failure, as with
+    /// `DropCheck::barrier_token`.
+    pub fn barrier(mut self, passed: Arc<AtomicBool>) -> Self {
+        self.barrier = Some(passed);
+        self
+    }
+
+    /// Ties the token to the current generation of a slot, as with
+    /// `DropCheck::generational_token_for`.
+    pub fn generation(mut self, generation: &Generation) -> Self {
+        self.generation = Some(generation.clone());
+        self
+    }
+
+    /// Gives the token a payload, as with `DropCheck::token_with_value`.
+    pub fn value<U>(self, value: U) -> TokenBuilder<'a, U> {
+        TokenBuilder {
+            dropcheck: self.dropcheck,
+            id: self.id,
+            name: self.name,
+            panic_on_drop: self.panic_on_drop,
+            expected_drops: self.expected_drops,
+            barrier: self.barrier,
+            generation: self.generation,
+            value,
+        }
+    }
+
+    /// Creates the token.
+    #[track_caller]
+    pub fn finish(self) -> DropToken<T> {
+        let set = &self.dropcheck.set;
+        let state = match self.id {
+            Some(id) => set.register_with_id(id),
+            None => set.register(),
+        };
+        if let Some(name) = self.name {
+            let _ = state.name.set(name);
+        }
+        state.panic_expected.store(self.panic_on_drop, Ordering::SeqCst);
+        state.expected_drops.store(self.expected_drops, Ordering::SeqCst);
+        *state.barrier.lock().unwrap_or_else(PoisonError::into_inner) = self.barrier;
+        let generation = self.generation.map(|generation| {
+            let current = generation.get();
+            (generation, current)
+        });
+        DropToken::from_parts(state, generation, self.value)
+    }
+}
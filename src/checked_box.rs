@@ -0,0 +1,89 @@
+//! A drop-checked replacement for `Box`.
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+use super::DropToken;
+
+/// A heap-allocated value whose drop is checked, like a `Box` that's also a `DropToken`.
+///
+/// Created by `DropCheck::checked_box`. Leaking the box is detected by its `DropCheck`, and
+/// dropping it twice panics, before the allocation could be freed twice. This lets code that
+/// stores owned elements in `Box`es be drop-checked by swapping in `CheckedBox`, without
+/// restructuring the tests around tokens.
+///
+/// # Examples
+///
+/// ```
+/// # use dropcheck::DropCheck;
+/// let dropcheck = DropCheck::new();
+///
+/// let mut b = dropcheck.checked_box(vec![1, 2, 3]);
+/// b.push(4);
+/// assert_eq!(*b, [1, 2, 3, 4]);
+///
+/// drop(b);
+/// assert!(dropcheck.all_dropped());
+/// ```
+///
+/// Freeing the box twice:
+///
+/// ```should_panic
+/// # use dropcheck::DropCheck;
+/// let dropcheck = DropCheck::new();
+/// let mut b = dropcheck.checked_box(String::from("hello"));
+///
+/// unsafe {
+///     std::ptr::drop_in_place(&mut b);
+///     std::ptr::drop_in_place(&mut b); // panics, without freeing the string again
+/// }
+/// ```
+pub struct CheckedBox<T> {
+    token: DropToken<Box<T>>,
+}
+
+impl<T> CheckedBox<T> {
+    pub(crate) fn new(token: DropToken<Box<T>>) -> Self {
+        Self { token }
+    }
+
+    /// Returns the id of the box's token.
+    pub fn id(&self) -> u64 {
+        self.token.id()
+    }
+
+    /// Consumes the box, returning the value in it.
+    ///
+    /// The box counts as dropped.
+    pub fn into_inner(self) -> T {
+        *self.token.into_inner()
+    }
+}
+
+impl<T> Deref for CheckedBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.token
+    }
+}
+
+impl<T> DerefMut for CheckedBox<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.token
+    }
+}
+
+/// Cloning a `CheckedBox` clones the value into a new box, tracked by the same set.
+impl<T: Clone> Clone for CheckedBox<T> {
+    #[track_caller]
+    fn clone(&self) -> Self {
+        Self { token: self.token.clone() }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for CheckedBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
@@ -0,0 +1,41 @@
+//! The thread-local default `DropCheck` set, used by `DropToken::new`.
+
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::sync::{Arc, Weak};
+
+use super::{DropCheck, Set};
+
+thread_local! {
+    /// The sets installed as the default on this thread, innermost last.
+    static DEFAULTS: RefCell<Vec<Weak<Set>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Returns the current default set of this thread, if any.
+pub(crate) fn current() -> Option<Arc<Set>> {
+    DEFAULTS.with(|defaults| defaults.borrow().last().and_then(Weak::upgrade))
+}
+
+/// Keeps a `DropCheck` installed as the thread's default set until it's dropped.
+///
+/// Created by `DropCheck::set_default`. Guards have to be dropped on the thread that created
+/// them, in the reverse order of their creation.
+#[derive(Debug)]
+#[must_use = "the set stops being the default when the guard is dropped"]
+pub struct DefaultGuard<'a> {
+    // Borrows the set, and isn't Send.
+    marker: PhantomData<(&'a DropCheck, *const ())>,
+}
+
+impl<'a> DefaultGuard<'a> {
+    pub(crate) fn new(dropcheck: &'a DropCheck) -> Self {
+        DEFAULTS.with(|defaults| defaults.borrow_mut().push(Arc::downgrade(&dropcheck.set)));
+        Self { marker: PhantomData }
+    }
+}
+
+impl Drop for DefaultGuard<'_> {
+    fn drop(&mut self) {
+        DEFAULTS.with(|defaults| defaults.borrow_mut().pop());
+    }
+}
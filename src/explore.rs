@@ -0,0 +1,70 @@
+//! Exploring every point at which a drop could panic.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::Ordering;
+
+use super::DropCheck;
+
+/// Runs `body` once for every drop it performs, making a different drop panic each time, and
+/// checks that no token was leaked or dropped more than once despite the panic.
+///
+/// `body` creates its tokens from the `DropCheck` it's given. On the first run the first drop of
+/// one of those tokens panics, on the second run the second drop, and so on, until a run
+/// completes with fewer drops than the panic point, or after `max` runs. The injected panics are
+/// caught, and printed by the panic hook as usual.
+///
+/// # Panics
+///
+/// Panics with the panic point and the problems found if a run leaks or over-drops a token.
+/// A panic from `body` before the injected panic happens is resumed, as it's a bug in the test.
+///
+/// # Examples
+///
+/// `Vec` keeps dropping its elements when one of them panics, so it's clean at every point:
+///
+/// ```
+/// dropcheck::explore_panics(100, |dropcheck| {
+///     let mut v: Vec<_> = (0 .. 5).map(|_| dropcheck.token()).collect();
+///     v.remove(1);
+///     drop(v);
+/// });
+/// ```
+///
+/// Whereas code that forgets the remaining elements when dropping one panics leaks them:
+///
+/// ```should_panic
+/// use std::mem::ManuallyDrop;
+///
+/// dropcheck::explore_panics(100, |dropcheck| {
+///     let mut v = ManuallyDrop::new((0 .. 5).map(|_| dropcheck.token()).collect::<Vec<_>>());
+///     while let Some(token) = v.pop() {
+///         drop(token);
+///     }
+///     unsafe { ManuallyDrop::drop(&mut v) };
+/// });
+/// // panics with "with a panic injected at drop #1: not all tokens dropped: ..."
+/// ```
+#[track_caller]
+pub fn explore_panics<F>(max: usize, mut body: F)
+    where F: FnMut(&DropCheck)
+{
+    for point in 0 .. max as u64 {
+        let dropcheck = DropCheck::new();
+        dropcheck.set.inject_panic.store(point + 1, Ordering::SeqCst);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| body(&dropcheck)));
+        let injected = dropcheck.set.next_drop_seq.load(Ordering::SeqCst) > point;
+        if let Err(payload) = result {
+            if !injected {
+                panic::resume_unwind(payload);
+            }
+        }
+
+        if let Err(err) = dropcheck.finish() {
+            panic!("with a panic injected at drop #{}: {}", point + 1, err);
+        }
+        if !injected {
+            return;
+        }
+    }
+}
@@ -0,0 +1,247 @@
+//! Fuzzing containers for drop bugs.
+//!
+//! `run` decodes a sequence of container operations from raw fuzzer input, applies them to a
+//! container of `DropToken`s, and checks after every operation that exactly the tokens the
+//! container should hold are still alive. With cargo-fuzz, a complete fuzz target is:
+//!
+//! ```ignore
+//! #![no_main]
+//! use libfuzzer_sys::fuzz_target;
+//!
+//! fuzz_target!(|data: &[u8]| {
+//!     dropcheck::fuzz::run(data, MyContainer::new());
+//! });
+//! ```
+//!
+//! where `MyContainer` implements `Container`.
+//!
+//! With the `arbitrary` feature, `Op` implements `arbitrary::Arbitrary`, so the fuzz target can
+//! take a `Vec<Op>` and pass it to `run_ops` instead. Raw input is still supported without it, as
+//! `Op::decode` needs no dependencies, and every input decodes to some sequence.
+
+use std::fmt;
+
+use super::{DropCheck, DropToken};
+
+/// An operation on a container of tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// Pushes a new token onto the end.
+    Push,
+    /// Pops the last token, if any.
+    Pop,
+    /// Inserts a new token at the index, modulo the number of possible positions.
+    Insert(usize),
+    /// Removes the token at the index, modulo the length, if the container isn't empty.
+    Remove(usize),
+    /// Clones the container, then drops the clone.
+    Clone,
+    /// Removes every token.
+    Clear,
+}
+
+impl Op {
+    /// Decodes a sequence of operations from raw fuzzer input.
+    ///
+    /// Each operation is one byte, followed by a second byte for the index of `Insert` and
+    /// `Remove`. Every input decodes to some sequence, so the fuzzer never wastes time on
+    /// invalid inputs.
+    ///
+    /// ```
+    /// use dropcheck::fuzz::Op;
+    ///
+    /// assert_eq!(Op::decode(&[0, 2, 7, 5]), vec![Op::Push, Op::Insert(7), Op::Clear]);
+    /// ```
+    pub fn decode(data: &[u8]) -> Vec<Op> {
+        let mut ops = Vec::new();
+        let mut bytes = data.iter().copied();
+        while let Some(tag) = bytes.next() {
+            let op = match tag % 6 {
+                0 => Op::Push,
+                1 => Op::Pop,
+                2 => Op::Insert(bytes.next().unwrap_or(0).into()),
+                3 => Op::Remove(bytes.next().unwrap_or(0).into()),
+                4 => Op::Clone,
+                _ => Op::Clear,
+            };
+            ops.push(op);
+        }
+        ops
+    }
+}
+
+/// Picks one of the operations uniformly, followed by an index for `Insert` and `Remove`.
+///
+/// ```
+/// use arbitrary::{Arbitrary, Unstructured};
+/// use dropcheck::fuzz::{self, Op};
+///
+/// let data = [0, 0, 2, 1, 4, 3, 0, 1, 5, 0];
+/// let ops = Vec::<Op>::arbitrary_take_rest(Unstructured::new(&data)).unwrap();
+/// fuzz::run_ops(&ops, Vec::new());
+/// ```
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Op {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(match u.int_in_range(0 ..= 5u8)? {
+            0 => Op::Push,
+            1 => Op::Pop,
+            2 => Op::Insert(u.arbitrary()?),
+            3 => Op::Remove(u.arbitrary()?),
+            4 => Op::Clone,
+            _ => Op::Clear,
+        })
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Op::Push => write!(f, "push"),
+            Op::Pop => write!(f, "pop"),
+            Op::Insert(index) => write!(f, "insert at {}", index),
+            Op::Remove(index) => write!(f, "remove at {}", index),
+            Op::Clone => write!(f, "clone"),
+            Op::Clear => write!(f, "clear"),
+        }
+    }
+}
+
+/// A container of tokens that can be fuzzed with `run`.
+///
+/// The indices passed to `insert` and `remove` are always in bounds.
+pub trait Container: Clone {
+    /// Appends a token.
+    fn push(&mut self, token: DropToken);
+
+    /// Removes and returns the last token, if any.
+    fn pop(&mut self) -> Option<DropToken>;
+
+    /// Inserts a token at `index`, shifting the following tokens.
+    fn insert(&mut self, index: usize, token: DropToken);
+
+    /// Removes and returns the token at `index`, shifting the following tokens.
+    fn remove(&mut self, index: usize) -> DropToken;
+
+    /// Drops every token.
+    fn clear(&mut self);
+
+    /// Returns the number of tokens in the container.
+    fn len(&self) -> usize;
+
+    /// Returns true if the container is empty.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Container for Vec<DropToken> {
+    fn push(&mut self, token: DropToken) {
+        Vec::push(self, token)
+    }
+
+    fn pop(&mut self) -> Option<DropToken> {
+        Vec::pop(self)
+    }
+
+    fn insert(&mut self, index: usize, token: DropToken) {
+        Vec::insert(self, index, token)
+    }
+
+    fn remove(&mut self, index: usize) -> DropToken {
+        Vec::remove(self, index)
+    }
+
+    fn clear(&mut self) {
+        Vec::clear(self)
+    }
+
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+}
+
+/// Applies the operations decoded from `data` to `container`, checking its drop behaviour after
+/// every one.
+///
+/// After each operation, the container must hold as many tokens as it should, and exactly that
+/// many tokens must still be alive. Finally, the container is dropped, and every token must have
+/// been dropped exactly once.
+///
+/// # Panics
+///
+/// Panics with the failing operation and its position in the sequence if a check fails.
+///
+/// # Examples
+///
+/// ```
+/// dropcheck::fuzz::run(&[0, 0, 2, 1, 4, 3, 0, 1, 5, 0], Vec::new());
+/// ```
+#[track_caller]
+pub fn run<C: Container>(data: &[u8], container: C) {
+    run_ops(&Op::decode(data), container)
+}
+
+/// Applies `ops` to `container`, checking its drop behaviour after every one, like `run`.
+///
+/// For fuzz targets that generate the operations themselves, such as with the `arbitrary`
+/// feature.
+///
+/// ```
+/// use dropcheck::fuzz::{self, Op};
+///
+/// fuzz::run_ops(&[Op::Push, Op::Push, Op::Remove(0), Op::Clone, Op::Clear], Vec::new());
+/// ```
+#[track_caller]
+pub fn run_ops<C: Container>(ops: &[Op], mut container: C) {
+    let dropcheck = DropCheck::new();
+    let mut len = 0;
+
+    for (i, &op) in ops.iter().enumerate() {
+        match op {
+            Op::Push => {
+                container.push(dropcheck.token());
+                len += 1;
+            },
+            Op::Pop => {
+                let token = container.pop();
+                if token.is_some() != (len > 0) {
+                    panic!("op #{} ({}): pop returned {:?} with {} tokens", i, op, token, len);
+                }
+                if token.is_some() {
+                    len -= 1;
+                }
+            },
+            Op::Insert(index) => {
+                container.insert(index % (len + 1), dropcheck.token());
+                len += 1;
+            },
+            Op::Remove(index) => {
+                if len > 0 {
+                    drop(container.remove(index % len));
+                    len -= 1;
+                }
+            },
+            Op::Clone => drop(container.clone()),
+            Op::Clear => {
+                container.clear();
+                len = 0;
+            },
+        }
+
+        if container.len() != len {
+            panic!("op #{} ({}): container has length {}, expected {}",
+                   i, op, container.len(), len);
+        }
+        let live = dropcheck.status_counts().live();
+        if live != len {
+            panic!("op #{} ({}): {} tokens alive, but the container should hold {}",
+                   i, op, live, len);
+        }
+    }
+
+    drop(container);
+    if let Err(err) = dropcheck.finish() {
+        panic!("{}", err);
+    }
+}
@@ -0,0 +1,138 @@
+//! Process-wide ledger of `DropCheck` sets.
+//!
+//! Sets opt in with `DropCheck::register_global`; `global_report` then summarizes the outcome of
+//! every registered set, including the ones that have already been dropped.
+
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+
+use super::Set;
+
+static LEDGER: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+
+#[derive(Debug)]
+enum Entry {
+    Live(Weak<Set>),
+    Finished(SetSummary),
+}
+
+pub(crate) fn register(set: &Arc<Set>) {
+    LEDGER.lock().unwrap_or_else(PoisonError::into_inner)
+          .push(Entry::Live(Arc::downgrade(set)));
+}
+
+pub(crate) fn finish(set: &Arc<Set>) {
+    let summary = set.summary();
+    let mut ledger = LEDGER.lock().unwrap_or_else(PoisonError::into_inner);
+    for entry in ledger.iter_mut() {
+        if let Entry::Live(live) = entry {
+            if live.as_ptr() == Arc::as_ptr(set) {
+                *entry = Entry::Finished(summary);
+                return;
+            }
+        }
+    }
+}
+
+/// Summarizes every `DropCheck` registered with `DropCheck::register_global` so far.
+///
+/// # Examples
+///
+/// ```
+/// # use dropcheck::DropCheck;
+/// {
+///     let dropcheck = DropCheck::new();
+///     dropcheck.register_global();
+///
+///     let _token = dropcheck.token();
+/// }
+///
+/// let report = dropcheck::global_report();
+/// assert!(report.is_clean());
+/// assert_eq!(report.sets()[0].created(), 1);
+/// ```
+pub fn global_report() -> GlobalReport {
+    let ledger = LEDGER.lock().unwrap_or_else(PoisonError::into_inner);
+    let sets = ledger.iter().filter_map(|entry| {
+        match entry {
+            Entry::Live(set) => set.upgrade().map(|set| (set.summary(), false)),
+            Entry::Finished(summary) => Some((summary.clone(), true)),
+        }
+    }).collect();
+    GlobalReport { sets }
+}
+
+/// Counts of the tokens in a single `DropCheck` set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetSummary {
+    pub(crate) created: usize,
+    pub(crate) dropped: usize,
+    pub(crate) leaked: usize,
+    pub(crate) over_dropped: usize,
+}
+
+impl SetSummary {
+    /// Returns the number of tokens created in the set.
+    pub fn created(&self) -> usize {
+        self.created
+    }
+
+    /// Returns the number of tokens that have been dropped at least once.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Returns the number of tokens that haven't been dropped, excluding intentional leaks.
+    pub fn leaked(&self) -> usize {
+        self.leaked
+    }
+
+    /// Returns the number of tokens that have been dropped more than once.
+    pub fn over_dropped(&self) -> usize {
+        self.over_dropped
+    }
+
+    /// Returns true if no token was leaked or dropped more than once.
+    pub fn is_clean(&self) -> bool {
+        self.leaked == 0 && self.over_dropped == 0
+    }
+}
+
+impl fmt::Display for SetSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} created, {} dropped, {} leaked, {} over-dropped",
+               self.created, self.dropped, self.leaked, self.over_dropped)
+    }
+}
+
+/// The outcome of every globally registered `DropCheck` set.
+///
+/// Created by `global_report`.
+#[derive(Debug, Clone)]
+pub struct GlobalReport {
+    sets: Vec<(SetSummary, bool)>,
+}
+
+impl GlobalReport {
+    /// Returns the summaries of the registered sets, in registration order.
+    ///
+    /// Sets that are still live are summarized as they currently are; the leak counts of those
+    /// only become final once the set is dropped.
+    pub fn sets(&self) -> Vec<&SetSummary> {
+        self.sets.iter().map(|(summary, _)| summary).collect()
+    }
+
+    /// Returns true if no registered set leaked or over-dropped any token.
+    pub fn is_clean(&self) -> bool {
+        self.sets.iter().all(|(summary, _)| summary.is_clean())
+    }
+}
+
+impl fmt::Display for GlobalReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (summary, finished)) in self.sets.iter().enumerate() {
+            writeln!(f, "set {}: {}{}", i, summary, if *finished { "" } else { " (still live)" })?;
+        }
+        Ok(())
+    }
+}
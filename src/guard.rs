@@ -0,0 +1,47 @@
+//! Checking a `DropCheck` set at the end of a scope, rather than when the set is dropped.
+
+use std::thread;
+
+use super::DropCheck;
+
+/// Checks its set when dropped, panicking if any token was leaked or dropped more than once.
+///
+/// Created by `DropCheck::guard`, which hands the check over from the set to the guard. The set
+/// itself can then live as long as needed, for instance in a static, while the check happens
+/// exactly where the guard goes out of scope.
+///
+/// # Examples
+///
+/// ```
+/// # use dropcheck::DropCheck;
+/// use std::sync::OnceLock;
+///
+/// static DROPCHECK: OnceLock<DropCheck> = OnceLock::new();
+/// let dropcheck = DROPCHECK.get_or_init(DropCheck::new);
+///
+/// {
+///     let _guard = dropcheck.guard();
+///     let v = vec![dropcheck.token(), dropcheck.token()];
+///     drop(v);
+/// } // checked here
+/// ```
+#[derive(Debug)]
+#[must_use = "the set is checked when the guard is dropped"]
+pub struct CheckGuard<'a> {
+    dropcheck: &'a DropCheck,
+}
+
+impl<'a> CheckGuard<'a> {
+    pub(crate) fn new(dropcheck: &'a DropCheck) -> Self {
+        dropcheck.disable_drop_assert();
+        Self { dropcheck }
+    }
+}
+
+impl Drop for CheckGuard<'_> {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            self.dropcheck.assert_clean();
+        }
+    }
+}
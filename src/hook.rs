@@ -0,0 +1,57 @@
+//! Opt-in panic hook that prints the full report of a failing `DropCheck` set.
+
+use std::cell::RefCell;
+use std::panic::{self, PanicHookInfo};
+use std::sync::Once;
+
+use super::{panic_message, DropReport};
+
+thread_local! {
+    /// The message and report of the `DropCheck` that's about to panic on this thread.
+    static PENDING: RefCell<Option<(String, DropReport)>> = const { RefCell::new(None) };
+}
+
+/// Records the report for a `DropCheck` that's about to panic with `message`.
+pub(crate) fn set_pending(message: &str, report: DropReport) {
+    PENDING.with(|pending| *pending.borrow_mut() = Some((message.to_owned(), report)));
+}
+
+fn take_pending(info: &PanicHookInfo) -> Option<DropReport> {
+    let (message, report) = PENDING.with(|pending| pending.borrow_mut().take())?;
+    if panic_message(info.payload()) == message {
+        Some(report)
+    } else {
+        None
+    }
+}
+
+/// Installs a panic hook that prints the report of a failing `DropCheck`.
+///
+/// When a `DropCheck` panics because its tokens were leaked or dropped more than once, the panic
+/// message only lists the failing tokens, and the location points into the `DropCheck`'s
+/// destructor. With this hook installed, the summary table of every token in the set is printed
+/// to stderr first. The hook then chains to the previously installed one, so the default output,
+/// and any other custom hook, are kept.
+///
+/// Installing the hook more than once has no further effect.
+///
+/// ```should_panic
+/// # use dropcheck::DropCheck;
+/// dropcheck::install_panic_hook();
+///
+/// let dropcheck = DropCheck::new();
+/// std::mem::forget(dropcheck.token());
+/// // prints the table of tokens, then panics as usual
+/// ```
+pub fn install_panic_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if let Some(report) = take_pending(info) {
+                eprint!("dropcheck report:\n{}", report);
+            }
+            previous(info);
+        }));
+    });
+}
@@ -29,25 +29,517 @@
 //!     std::ptr::drop_in_place(&mut token); // panics
 //! }
 //! ```
+//!
+//! # Platform support
+//!
+//! On `wasm32-unknown-unknown`, which can't spawn threads or read the clock, `stress` and
+//! `DropCheck::watchdog` aren't available and the `timing` feature can't be enabled.
+//!
+//! That target also aborts on panic rather than unwinding. Leaks and double drops are still
+//! detected, but the panic reporting them ends the whole test run. Everything built on catching
+//! panics is affected:
+//!
+//! * `DropCheck::panicking_token` and `TokenBuilder::panic_on_drop` abort when dropped, so a
+//!   container's handling of a panicking element can't be tested.
+//! * Panics while dropping a token aren't recorded, as the process is gone by then;
+//!   `DropCheck::any_drop_panicked` and `DropCheck::panicked_drops` never see one.
+//! * `DropCheck::minimize` aborts on the first replay that panics.
+//! * `explore_panics` aborts on the first injected panic.
+//! * `DropCheck::assert_clean_on_unwind` can't check the set after `f` panics.
+//!
+//! # Metrics
+//!
+//! With the `metrics` feature, every set reports its counts through the `metrics` facade, to
+//! whichever recorder is installed. Long-running stress and soak tests can then chart drop
+//! behavior alongside their other metrics:
+//!
+//! * `dropcheck_tokens_alive`: a gauge of the tokens that haven't been dropped yet.
+//! * `dropcheck_tokens_created`: a counter of the tokens created.
+//! * `dropcheck_tokens_dropped`: a counter of the tokens dropped at least once.
+//! * `dropcheck_violations`: a counter of the violations logged, such as double drops.
+//!
+//! Sets created with `DropCheck::named` label their metrics with `set` = their name.
+//!
+#![cfg_attr(feature = "metrics", doc = "```")]
+#![cfg_attr(not(feature = "metrics"), doc = "```ignore")]
+//! # use std::{collections::BTreeMap, sync::{Arc, Mutex, atomic::{AtomicU64, Ordering}}};
+//! # use metrics::{Counter, CounterFn, Gauge, GaugeFn, Histogram, Key, KeyName, Metadata, Recorder, SharedString, Unit};
+//! # use dropcheck::DropCheck;
+//! # #[derive(Default)]
+//! # struct Value(AtomicU64);
+//! # impl CounterFn for Value {
+//! #     fn increment(&self, value: u64) { self.0.fetch_add(value, Ordering::SeqCst); }
+//! #     fn absolute(&self, value: u64) { self.0.store(value, Ordering::SeqCst); }
+//! # }
+//! # impl GaugeFn for Value {
+//! #     fn increment(&self, _: f64) { unimplemented!() }
+//! #     fn decrement(&self, _: f64) { unimplemented!() }
+//! #     fn set(&self, value: f64) { self.0.store(value as u64, Ordering::SeqCst); }
+//! # }
+//! # #[derive(Default)]
+//! # struct Values(Mutex<BTreeMap<String, Arc<Value>>>);
+//! # impl Values {
+//! #     fn value(&self, key: &Key) -> Arc<Value> {
+//! #         let labels: Vec<_> = key.labels().map(|label| format!("{}={}", label.key(), label.value())).collect();
+//! #         let name = format!("{}{{{}}}", key.name(), labels.join(","));
+//! #         Arc::clone(self.0.lock().unwrap().entry(name).or_default())
+//! #     }
+//! #     fn get(&self, name: &str) -> u64 { self.0.lock().unwrap()[name].0.load(Ordering::SeqCst) }
+//! # }
+//! # impl Recorder for Values {
+//! #     fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+//! #     fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+//! #     fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+//! #     fn register_counter(&self, key: &Key, _: &Metadata) -> Counter { Counter::from_arc(self.value(key)) }
+//! #     fn register_gauge(&self, key: &Key, _: &Metadata) -> Gauge { Gauge::from_arc(self.value(key)) }
+//! #     fn register_histogram(&self, _: &Key, _: &Metadata) -> Histogram { Histogram::noop() }
+//! # }
+//! let recorder = Values::default();
+//! metrics::with_local_recorder(&recorder, || {
+//!     let dropcheck = DropCheck::named("vec");
+//!     let mut tokens = vec![dropcheck.token(), dropcheck.token(), dropcheck.token()];
+//!     tokens.pop();
+//!
+//!     assert_eq!(recorder.get("dropcheck_tokens_created{set=vec}"), 3);
+//!     assert_eq!(recorder.get("dropcheck_tokens_dropped{set=vec}"), 1);
+//!     assert_eq!(recorder.get("dropcheck_tokens_alive{set=vec}"), 2);
+//! });
+//! ```
 
+use std::any::Any;
+use std::cmp;
+use std::collections::BTreeMap;
 use std::fmt;
-use std::sync::{Arc, Weak, RwLock, atomic::{AtomicUsize, Ordering}};
+use std::fs::File;
+use std::io::{self, Write};
+use std::mem::{self, ManuallyDrop};
+use std::ops::{Deref, DerefMut};
+use std::panic::{self, AssertUnwindSafe, Location, UnwindSafe};
+use std::path::Path;
+use std::sync::{Arc, Weak, Mutex, OnceLock, PoisonError, RwLock, atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering}};
+#[cfg(feature = "async")]
+use std::future::{self, Future};
+#[cfg(feature = "async")]
+use std::task::{Context, Poll, Waker};
+use std::thread;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::time::Duration;
+#[cfg(feature = "timing")]
+use std::time::Instant;
+
+#[cfg(feature = "alloc")]
+pub mod alloc;
+
+mod borrow;
+pub use borrow::{BorrowTarget, BorrowToken};
+
+mod builder;
+pub use builder::TokenBuilder;
+
+mod checked_box;
+pub use checked_box::CheckedBox;
+
+mod default;
+pub use default::DefaultGuard;
+
+pub mod fuzz;
+
+mod explore;
+pub use explore::explore_panics;
+
+mod global;
+pub use global::{global_report, GlobalReport, SetSummary};
+
+mod guard;
+pub use guard::CheckGuard;
+
+mod hook;
+pub use hook::install_panic_hook;
+
+pub mod model;
+
+mod probe;
+pub use probe::{Auto, NotSend, NotSync, NotUnpin, ProbeToken};
+
+mod registry;
+pub use registry::{DropCheckRegistry, RegistryError};
+
+mod report;
+pub use report::{triage, BaselineDiff, DropError, DropReport, DropSnapshot, LeakFrequency, ReportDiff,
+                 StatusCounts, ThreadStats, TokenChange, TokenReport};
+#[cfg(feature = "timing")]
+pub use report::LifetimeStats;
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+mod stress;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use stress::stress;
+
+mod track;
+pub use track::{TrackDrop, Tracker};
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+mod watchdog;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use watchdog::Watchdog;
+
+#[cfg(all(feature = "timing", target_arch = "wasm32", target_os = "unknown"))]
+compile_error!("the timing feature needs std::time::Instant, which wasm32-unknown-unknown lacks");
 
 /// A drop-checking token.
 ///
-/// Created by `DropCheck`.
+/// Created by `DropCheck`. A token can optionally carry a payload of type `T`, which it derefs to;
+/// see `DropCheck::token_with_value`.
+// The fields are only released by the last expected drop of a token, so that dropping a token
+// more than expected - which is what this crate exists to detect - doesn't also release them more
+// than once, and earlier drops of tokens expected to be dropped several times can still use them.
+//
+// Any further drop still reads `state`, so this is only sound while something other than the
+// token owns the `DropState`: its set, which keeps every state it ever created alive until it's
+// dropped, and then keeps those whose tokens are still live. Tokens without a set leak their
+// state instead. `Drop` debug-asserts this before releasing the token's own reference.
 #[derive(Debug)]
-pub struct DropToken {
-    set: Weak<RwLock<Vec<Arc<DropState>>>>,
-    state: Arc<DropState>,
+pub struct DropToken<T = ()> {
+    state: ManuallyDrop<Arc<DropState>>,
+    generation: ManuallyDrop<Option<(Generation, u64)>>,
+    value: ManuallyDrop<T>,
 }
 
-impl Drop for DropToken {
+impl<T> Drop for DropToken<T> {
     fn drop(&mut self) {
-        self.state.set_dropped();
+        // Any panic while dropping the token - including one from dropping its payload - is
+        // recorded in its state before being resumed.
+        let state = Arc::clone(&self.state);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let drops = self.mark_dropped();
+            let mismatch = self.generation_mismatch().or_else(|| self.barrier_not_passed());
+
+            if drops == state.expected_drops() {
+                // Besides the token's own reference and `state`, something must keep the state
+                // alive for any later drop to read.
+                debug_assert!(Arc::strong_count(&state) > 2,
+                              "{} released its state's last owner", state);
+
+                // SAFETY: only the last expected drop of a token gets here, so each field is
+                // released once.
+                unsafe {
+                    ManuallyDrop::drop(&mut self.value);
+                    ManuallyDrop::drop(&mut self.generation);
+                    ManuallyDrop::drop(&mut self.state);
+                }
+            }
+
+            if let Some(mismatch) = mismatch {
+                panic!("{}", mismatch);
+            }
+            if drops == 1 && state.panic_expected.load(Ordering::SeqCst) {
+                panic!("intentional panic dropping {}", state);
+            }
+            drops
+        }));
+
+        match result {
+            Ok(drops) if drops > state.expected_drops() => {
+                if let Some(set) = state.set() {
+                    set.log_violation(&format!("{} dropped again", state));
+                }
+            },
+            Ok(_) => {},
+            Err(payload) => {
+                if !state.panic_expected.load(Ordering::SeqCst) {
+                    if let Some(set) = state.set() {
+                        set.log_violation(panic_message(&*payload));
+                    }
+                }
+                state.drop_panicked.store(true, Ordering::SeqCst);
+                panic::resume_unwind(payload);
+            },
+        }
+    }
+}
+
+impl<T> Deref for DropToken<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for DropToken<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl DropToken {
+    /// Creates a new token in the thread's default set.
+    ///
+    /// Lets deeply nested test helpers create tokens without a `&DropCheck` being passed down to
+    /// them. See `DropCheck::set_default`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no set is installed as the default on this thread.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::{DropCheck, DropToken};
+    /// fn make_elements() -> Vec<DropToken> {
+    ///     (0 .. 10).map(|_| DropToken::new()).collect()
+    /// }
+    ///
+    /// let dropcheck = DropCheck::new();
+    /// let _guard = dropcheck.set_default();
+    ///
+    /// let v = make_elements();
+    /// assert!(dropcheck.none_dropped());
+    /// drop(v);
+    /// assert!(dropcheck.all_dropped());
+    /// ```
+    #[track_caller]
+    pub fn new() -> Self {
+        match default::current() {
+            Some(set) => Self::from_parts(set.register(), None, ()),
+            None => panic!("no default DropCheck set on this thread"),
+        }
+    }
+}
+
+/// Same as `DropToken::new`.
+impl Default for DropToken {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> DropToken<T> {
+    fn from_parts(state: Arc<DropState>, generation: Option<(Generation, u64)>, value: T) -> Self {
+        Self {
+            state: ManuallyDrop::new(state),
+            generation: ManuallyDrop::new(generation),
+            value: ManuallyDrop::new(value),
+        }
+    }
+
+    /// Marks the state as dropped, returning how many times it has been dropped.
+    fn mark_dropped(&self) -> usize {
+        let set = self.state.set();
+        if let Some(set) = &set {
+            // A retired state was already dropped as many times as expected, so dropping it again
+            // is an over-drop, which must show up in the set's checks.
+            if self.state.drop_count() >= self.state.expected_drops()
+                && self.state.retired.swap(false, Ordering::SeqCst)
+            {
+                set.readmit(&self.state);
+            }
+        }
+        let drop_seq = set.as_ref().map(|set| set.next_drop_seq.fetch_add(1, Ordering::SeqCst));
+        if let (Some(set), Some(drop_seq)) = (&set, drop_seq) {
+            if set.inject_panic.load(Ordering::SeqCst) == drop_seq + 1 {
+                self.state.panic_expected.store(true, Ordering::SeqCst);
+            }
+        }
+        let drops = self.state.set_dropped(set.as_deref(), drop_seq);
+
+        if let Some(set) = &set {
+            set.log_event(&format!("dropped {}", **self.state));
+        }
+        #[cfg(feature = "async")]
+        if let Some(set) = set {
+            set.wake();
+        }
+
+        drops
+    }
+
+    fn generation_mismatch(&self) -> Option<String> {
+        match &*self.generation {
+            Some((generation, expected)) if generation.get() != *expected => {
+                Some(format!("{}ABA: generation mismatch: {} is from generation {}, but its slot is at generation {}",
+                             self.state.prefix(), **self.state, expected, generation.get()))
+            },
+            _ => None,
+        }
+    }
+
+    fn barrier_not_passed(&self) -> Option<String> {
+        match &*self.state.barrier.lock().unwrap_or_else(PoisonError::into_inner) {
+            Some(passed) if !passed.load(Ordering::SeqCst) => {
+                Some(format!("{}{} dropped before its barrier was passed",
+                             self.state.prefix(), **self.state))
+            },
+            _ => None,
+        }
+    }
+
+    /// Moves the state and payload out of the token without running its destructor.
+    fn into_parts(self) -> (Arc<DropState>, T) {
+        let mut this = ManuallyDrop::new(self);
+
+        // SAFETY: `this` is never used again, and its destructor never runs, so each field is
+        // moved out exactly once.
+        unsafe {
+            ManuallyDrop::drop(&mut this.generation);
+            (ManuallyDrop::take(&mut this.state), ManuallyDrop::take(&mut this.value))
+        }
+    }
+
+    /// Returns the id of this token's state.
+    pub fn id(&self) -> u64 {
+        self.state.id()
+    }
+
+    /// Records that the token was moved.
+    ///
+    /// Moves aren't observable in safe Rust, so this is opt-in instrumentation: call it from
+    /// container code wherever an element is relocated, and read the total back with
+    /// `DropState::move_count`. That lets a test assert that a container doesn't move its elements
+    /// more than necessary:
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    /// let (token, state) = dropcheck.pair();
+    ///
+    /// let mut v = vec![token];
+    /// v.reserve(100);
+    /// for token in &v {
+    ///     token.mark_moved();
+    /// }
+    /// assert_eq!(state.move_count(), 1);
+    /// ```
+    pub fn mark_moved(&self) {
+        self.state.move_count.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Leaks the token on purpose, returning its state.
+    ///
+    /// The state is excluded from the leak assertion of the `DropCheck` the token came from. Use
+    /// this for tokens that are deliberately leaked into static storage, and check that they were
+    /// never dropped with the returned state. The payload, if any, is leaked along with the token.
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    /// let state = dropcheck.token().leak_intentionally();
+    ///
+    /// assert!(dropcheck.all_dropped());
+    /// assert!(state.is_not_dropped());
+    /// ```
+    pub fn leak_intentionally(self) -> Arc<DropState> {
+        if let Some(set) = self.state.set() {
+            set.remove_counts(&self.state);
+            self.state.leak_expected.store(true, Ordering::SeqCst);
+            set.add_counts(&self.state);
+        } else {
+            self.state.leak_expected.store(true, Ordering::SeqCst);
+        }
+        let (state, value) = self.into_parts();
+        mem::forget(value);
+
+        #[cfg(feature = "async")]
+        if let Some(set) = state.set() {
+            set.wake();
+        }
+
+        state
+    }
+
+    /// Consumes the token, returning its payload without dropping it.
+    ///
+    /// The token's state is marked as dropped, exactly as if the token itself had been dropped.
+    /// This models a container removing and returning an element, like `Vec::pop`:
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    ///
+    /// let mut v = vec![dropcheck.token_with_value(String::from("hello"))];
+    /// let s = v.pop().unwrap().into_inner();
+    ///
+    /// assert_eq!(s, "hello");
+    /// assert!(dropcheck.all_dropped());
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics in the same cases dropping the token would, such as a generational token whose slot
+    /// was reused:
+    ///
+    /// ```should_panic
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    ///
+    /// let (stale, generation) = dropcheck.generational_token();
+    /// generation.advance();
+    ///
+    /// stale.into_inner(); // panics with "ABA: generation mismatch"
+    /// ```
+    pub fn into_inner(self) -> T {
+        self.mark_dropped();
+        let mismatch = self.generation_mismatch().or_else(|| self.barrier_not_passed());
+
+        // The token must be taken apart before panicking, or its destructor would run again
+        // during the unwind.
+        let (_, value) = self.into_parts();
+        if let Some(mismatch) = mismatch {
+            panic!("{}", mismatch);
+        }
+        value
+    }
+
+    /// Converts the token into a raw pointer, like `Box::into_raw`.
+    ///
+    /// The token is still live: convert it back with `from_raw` to drop it. Leaking the raw
+    /// pointer instead leaks the token, which is detected as usual.
+    ///
+    /// The token is moved into a small allocation, which `from_raw` frees.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::{DropCheck, DropToken};
+    /// let dropcheck = DropCheck::new();
+    /// let (token, state) = dropcheck.pair();
+    ///
+    /// let ptr = token.into_raw();
+    /// assert!(state.is_not_dropped());
+    ///
+    /// drop(unsafe { DropToken::from_raw(ptr) });
+    /// assert!(state.is_dropped());
+    /// ```
+    #[cfg(feature = "raw")]
+    pub fn into_raw(self) -> *mut DropTokenInner<T> {
+        Box::into_raw(Box::new(DropTokenInner { token: self }))
+    }
+
+    /// Converts a raw pointer created by `into_raw` back into a token.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `into_raw`, for a token with the same payload type, and
+    /// must not have been passed to `from_raw` already, as its allocation is freed. Like with
+    /// `Box::from_raw`, reconstructing a token twice is undefined behavior.
+    #[cfg(feature = "raw")]
+    pub unsafe fn from_raw(ptr: *mut DropTokenInner<T>) -> Self {
+        // SAFETY: the caller guarantees that ptr came from into_raw, and is only reconstructed
+        // once.
+        unsafe { Box::from_raw(ptr) }.token
     }
 }
 
+/// The allocation behind a raw token pointer.
+///
+/// Created by `DropToken::into_raw`.
+#[cfg(feature = "raw")]
+#[derive(Debug)]
+pub struct DropTokenInner<T = ()> {
+    token: DropToken<T>,
+}
+
 /// Cloning a `DropToken` creates a fresh state, that's still tied to the `DropCheck` set that
 /// created the token. This means that leaking the cloned token is detected:
 ///
@@ -74,180 +566,2675 @@ impl Drop for DropToken {
 /// drop(cloned_token);
 /// assert!(!dropcheck.none_dropped());
 /// ```
-impl Clone for DropToken {
+impl<T: Clone> Clone for DropToken<T> {
+    #[track_caller]
     fn clone(&self) -> Self {
-        let state = DropState::new();
-        if let Some(set) = self.set.upgrade() {
-            set.write().unwrap().push(Arc::clone(&state));
-            Self {
-                set: Arc::downgrade(&set),
-                state,
-            }
-        } else {
-            Self {
-                set: Weak::new(),
-                state,
-            }
+        match self.state.set() {
+            Some(set) => Self::from_parts(set.register(), None, T::clone(&self.value)),
+            None => {
+                // Nothing else owns the state, so it's leaked to keep it alive past the clone's
+                // drop, as a second drop must still find it.
+                let state = DropState::new(u64::MAX, self.state.options);
+                mem::forget(Arc::clone(&state));
+                Self::from_parts(state, None, T::clone(&self.value))
+            },
+        }
+    }
+}
+
+/// The generation of a slot in a container that reuses slots, for detecting ABA bugs.
+///
+/// Created by `DropCheck::generational_token`.
+///
+/// Generational tokens remember the generation their slot was at when they were created. Advancing
+/// the generation models the slot being reused for a new value: by then the token for the previous
+/// value must have been dropped. If a token is dropped while its slot is at a different generation
+/// than the one the token was created in, the drop panics with "ABA: generation mismatch".
+#[derive(Debug, Clone, Default)]
+pub struct Generation {
+    current: Arc<AtomicU64>,
+}
+
+impl Generation {
+    /// Returns the current generation.
+    pub fn get(&self) -> u64 {
+        self.current.load(Ordering::SeqCst)
+    }
+
+    /// Advances to the next generation, returning it.
+    pub fn advance(&self) -> u64 {
+        self.current.fetch_add(1, Ordering::SeqCst) + 1
+    }
+}
+
+/// A handle to the state of the next token created in a `DropCheck` set.
+///
+/// Created by `DropCheck::watch_next`. The handle is bound to exactly the next token created in
+/// the set after the call, by any method. Querying the handle before that token exists is a bug,
+/// and panics.
+#[derive(Debug, Clone, Default)]
+pub struct DropWatch {
+    state: Arc<Mutex<Option<Arc<DropState>>>>,
+}
+
+impl DropWatch {
+    /// Returns the state of the watched token, or `None` if it hasn't been created yet.
+    pub fn state(&self) -> Option<Arc<DropState>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    /// Returns true if the watched token has been dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no token has been created since the watch was.
+    #[track_caller]
+    pub fn is_dropped(&self) -> bool {
+        match self.state() {
+            Some(state) => state.is_dropped(),
+            None => panic!("no token created since watch_next was called"),
         }
     }
+
+    /// The inverse of `is_dropped()`.
+    #[track_caller]
+    pub fn is_not_dropped(&self) -> bool {
+        !self.is_dropped()
+    }
 }
 
 /// The state of a particular `DropToken`.
 pub struct DropState {
+    id: AtomicU64,
+    set: Mutex<Weak<Set>>,
+    name: OnceLock<String>,
+    location: &'static Location<'static>,
+    options: Options,
     count: AtomicUsize,
+    expected_drops: AtomicUsize,
+    move_count: AtomicUsize,
+    drop_seq: AtomicU64,
+    created_on: Option<String>,
+    dropped_on: Mutex<Option<String>>,
+    leak_expected: AtomicBool,
+    panic_expected: AtomicBool,
+    drop_panicked: AtomicBool,
+    // Set while the state is removed from its set by `retain_live` or `reset_for_next_case`, until
+    // it's dropped again.
+    retired: AtomicBool,
+    barrier: Mutex<Option<Arc<AtomicBool>>>,
+    #[cfg(feature = "timing")]
+    created_at: Instant,
+    #[cfg(feature = "timing")]
+    lifetime: Mutex<Option<Duration>>,
+    #[cfg(feature = "async")]
+    wakers: Mutex<Vec<Waker>>,
 }
 
 impl fmt::Debug for DropState {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_struct(&format!("DropState<{:p}>", self))
-            .field("count", &self.count)
-            .finish()
+        let mut debug = f.debug_struct(&format!("DropState<{:p}>", self));
+        debug.field("id", &self.id())
+             .field("count", &self.count);
+        if let Some(created_on) = &self.created_on {
+            debug.field("created_on", created_on)
+                 .field("dropped_on",
+                        &*self.dropped_on.lock().unwrap_or_else(PoisonError::into_inner));
+        }
+        debug.finish()
+    }
+}
+
+/// Describes the token, including its name if it has one, and the threads it was created and
+/// dropped on if thread naming was enabled for its `DropCheck`.
+impl fmt::Display for DropState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&TokenReport::new(self), f)
     }
 }
 
 impl Drop for DropState {
     fn drop(&mut self) {
-        match self.count.get_mut() {
-            1 => {},
+        if thread::panicking() {
+            return;
+        }
+        match *self.count.get_mut() {
+            x if x == *self.expected_drops.get_mut() => {},
+            _ if self.options.lenient => {},
+            0 if *self.leak_expected.get_mut() => {},
             0 => panic!("token not dropped"),
-            _ => panic!("invalid drop count: {}"),
+            1 => panic!("token dropped only once"),
+            x if x < *self.expected_drops.get_mut() => panic!("token dropped only {} times", x),
+            x => panic!("invalid drop count: {}", x),
         }
     }
 }
 
 impl DropState {
+    /// Returns the id of this state.
+    ///
+    /// Unless chosen with `DropCheck::token_with_id`, ids are assigned in creation order, starting
+    /// from zero, and are unique within a `DropCheck` set. Tokens created by
+    /// `DropCheck::unregistered_token`, or cloned after their set was dropped, don't belong to any
+    /// set and get an id of `u64::MAX`. A token moved to another set with `DropCheck::adopt` gets
+    /// a new id from that set.
+    pub fn id(&self) -> u64 {
+        self.id.load(Ordering::SeqCst)
+    }
+
+    /// Returns the name of the token, if it has one.
+    pub fn name(&self) -> Option<&str> {
+        self.name.get().map(String::as_str)
+    }
+
+    /// Returns where the token was created.
+    ///
+    /// That's the caller of the `DropCheck` method that created it, or of `clone` for clones.
+    /// Leak reports include it, so they point at the token that wasn't dropped.
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    /// let (token, state) = dropcheck.pair();
+    /// assert_eq!(state.location().line(), line!() - 1);
+    ///
+    /// std::mem::forget(token);
+    /// let err = dropcheck.check().unwrap_err();
+    /// assert!(err.to_string().ends_with(&format!("created at {}:{}", file!(), line!() - 5)));
+    /// # dropcheck.disable_drop_assert();
+    /// ```
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+
     /// Returns true if the token associated with this state has been dropped.
     pub fn is_dropped(&self) -> bool {
         !self.is_not_dropped()
     }
 
+    /// Returns the number of times the token associated with this state has been dropped.
+    ///
+    /// Unlike `is_dropped` and `is_not_dropped`, this never panics, even if the token was dropped
+    /// more than once.
+    pub fn drop_count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of times the token is expected to be dropped.
+    ///
+    /// That's once, unless chosen with `TokenBuilder::expected_drops`.
+    pub fn expected_drops(&self) -> usize {
+        self.expected_drops.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of times the token was marked as moved with `DropToken::mark_moved`.
+    pub fn move_count(&self) -> usize {
+        self.move_count.load(Ordering::SeqCst)
+    }
+
     /// The inverse of `is_dropped()`.
     pub fn is_not_dropped(&self) -> bool {
         match self.count.load(Ordering::SeqCst) {
             0 => true,
-            1 => false,
+            x if x <= self.expected_drops() => false,
             x => panic!("invalid drop count: {}", x),
         }
     }
 
-    fn new() -> Arc<Self> {
-        Arc::new(Self { count: AtomicUsize::new(0) })
+    /// Returns true if the set the token belongs to still exists.
+    ///
+    /// False once the `DropCheck` has been dropped, and for tokens that never belonged to a set.
+    pub fn is_set_alive(&self) -> bool {
+        self.set().is_some()
     }
 
-    fn set_dropped(&self) {
-        match self.count.swap(1, Ordering::SeqCst) {
-            0 => {},
-            1 => panic!("already dropped"),
-            x => panic!("invalid drop count: {}", x),
-        }
+    /// Creates a weak handle to the state, which doesn't keep it alive.
+    ///
+    /// A strong `Arc<DropState>` held past the end of a test keeps the state around, and asserts
+    /// that its token was dropped whenever and wherever the last reference goes away. A weak
+    /// handle doesn't, so it suits observers on background threads that may outlive the test.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::{DropCheck, DropState};
+    /// use std::thread;
+    ///
+    /// let dropcheck = DropCheck::new();
+    /// let (token, state) = dropcheck.pair();
+    /// let weak = DropState::downgrade(&state);
+    /// drop(state);
+    ///
+    /// let observer = thread::spawn(move || {
+    ///     while weak.is_set_alive() {
+    ///         thread::yield_now();
+    ///     }
+    ///     weak.upgrade().is_none()
+    /// });
+    ///
+    /// drop(token);
+    /// drop(dropcheck);
+    /// assert!(observer.join().unwrap());
+    /// ```
+    pub fn downgrade(this: &Arc<Self>) -> WeakDropState {
+        WeakDropState { state: Arc::downgrade(this) }
     }
-}
 
-/// A set of `DropToken`'s.
-#[derive(Debug, Default)]
-pub struct DropCheck {
-    set: Arc<RwLock<Vec<Arc<DropState>>>>,
-}
+    #[track_caller]
+    fn new(id: u64, options: Options) -> Arc<Self> {
+        Arc::new(Self {
+            id: AtomicU64::new(id),
+            set: Mutex::new(Weak::new()),
+            name: OnceLock::new(),
+            location: Location::caller(),
+            count: AtomicUsize::new(0),
+            expected_drops: AtomicUsize::new(1),
+            move_count: AtomicUsize::new(0),
+            drop_seq: AtomicU64::new(u64::MAX),
+            options,
+            created_on: if options.thread_naming { Some(current_thread_name()) } else { None },
+            dropped_on: Mutex::new(None),
+            leak_expected: AtomicBool::new(false),
+            panic_expected: AtomicBool::new(false),
+            drop_panicked: AtomicBool::new(false),
+            retired: AtomicBool::new(false),
+            barrier: Mutex::new(None),
+            #[cfg(feature = "timing")]
+            created_at: Instant::now(),
+            #[cfg(feature = "timing")]
+            lifetime: Mutex::new(None),
+            #[cfg(feature = "async")]
+            wakers: Mutex::new(Vec::new()),
+        })
+    }
 
-impl Drop for DropCheck {
-    fn drop(&mut self) {
-        assert!(self.all_dropped(), "not all tokens dropped");
+    /// Creates a state that has already been dropped `count` times, without a token.
+    ///
+    /// For testing report logic with `DropCheck::from_states`. The state doesn't belong to any
+    /// set, so its id is `u64::MAX`, and it never panics when it's dropped itself, whatever its
+    /// count.
+    #[cfg(feature = "testing")]
+    #[track_caller]
+    pub fn with_count(count: usize) -> Arc<Self> {
+        let state = Self::new(u64::MAX, Options { lenient: true, ..Options::default() });
+        state.count.store(count, Ordering::SeqCst);
+        state
     }
-}
 
-impl DropCheck {
-    /// Creates a new `DropCheck` set.
-    pub fn new() -> Self {
-        Self::default()
+    /// Returns the set the state currently belongs to, if any.
+    fn set(&self) -> Option<Arc<Set>> {
+        self.set.lock().unwrap_or_else(PoisonError::into_inner).upgrade()
     }
 
-    fn push(&self, state: Arc<DropState>) {
-        self.set.write().unwrap().push(state)
+    /// Returns the prefix of messages about this state, naming its set if it has a name.
+    fn prefix(&self) -> String {
+        self.set().map(|set| set.prefix()).unwrap_or_default()
     }
 
-    /// Creates a new `DropToken`, whose state is part of this set.
-    pub fn token(&self) -> DropToken {
-        let state = DropState::new();
-        self.push(Arc::clone(&state));
+    fn is_leaked(&self) -> bool {
+        self.count.load(Ordering::SeqCst) < self.expected_drops()
+            && !self.leak_expected.load(Ordering::SeqCst)
+    }
+
+    fn is_over_dropped(&self) -> bool {
+        self.count.load(Ordering::SeqCst) > self.expected_drops()
+    }
 
-        DropToken {
-            set: Arc::downgrade(&self.set),
-            state,
+    fn drop_seq(&self) -> Option<u64> {
+        match self.drop_seq.load(Ordering::SeqCst) {
+            u64::MAX => None,
+            seq => Some(seq),
         }
     }
 
-    /// Creates a new `DropToken`, and also gives you a handle to the state.
+    /// Returns how long the token lived, from its creation until it was first dropped.
     ///
-    /// # Examples
+    /// Returns `None` if the token hasn't been dropped yet.
+    #[cfg(feature = "timing")]
+    pub fn lifetime(&self) -> Option<Duration> {
+        *self.lifetime.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Asserts that the token was dropped, and lived for at least `min` and at most `max`.
     ///
-    /// Checking when an operation drops a value:
+    /// Useful to check the eviction timing of TTL-based containers, where both premature and
+    /// delayed eviction are bugs.
+    ///
+    /// # Examples
     ///
     /// ```
     /// # use dropcheck::DropCheck;
-    /// let dropcheck = DropCheck::new();
+    /// use std::time::Duration;
     ///
-    /// let mut v = vec![dropcheck.token(); 10];
+    /// let dropcheck = DropCheck::new();
+    /// let (token, state) = dropcheck.pair();
     ///
-    /// let (t1, s1) = dropcheck.pair();
+    /// std::thread::sleep(Duration::from_millis(10));
+    /// drop(token);
+    /// state.assert_lifetime_within(Duration::from_millis(10), Duration::from_secs(60));
+    /// ```
+    #[cfg(feature = "timing")]
+    #[track_caller]
+    pub fn assert_lifetime_within(&self, min: Duration, max: Duration) {
+        match self.lifetime() {
+            None => panic!("{} not dropped", self),
+            Some(lifetime) if lifetime < min || lifetime > max => {
+                panic!("{} lived for {:?}, expected between {:?} and {:?}", self, lifetime, min, max)
+            },
+            Some(_) => {},
+        }
+    }
+
+    /// Marks the state as dropped, returning how many times it has been dropped, including this
+    /// time.
+    ///
+    /// `set` is the set the state belongs to, whose counts are updated.
+    fn set_dropped(&self, set: Option<&Set>, drop_seq: Option<u64>) -> usize {
+        let prev = self.count.fetch_add(1, Ordering::SeqCst);
+        let expected = self.expected_drops();
+        if let Some(set) = set.filter(|_| !self.retired.load(Ordering::SeqCst)) {
+            if prev == 0 {
+                set.dropped.fetch_add(1, Ordering::SeqCst);
+                #[cfg(feature = "metrics")]
+                metrics::counter!("dropcheck_tokens_dropped", set.metric_labels()).increment(1);
+            }
+            if prev + 1 == expected && !self.leak_expected.load(Ordering::SeqCst) {
+                set.live.fetch_sub(1, Ordering::SeqCst);
+                #[cfg(feature = "metrics")]
+                set.publish_live();
+            }
+            if prev == expected {
+                set.over_dropped.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        if prev != 0 {
+            if prev < expected {
+                return prev + 1;
+            }
+            match &self.created_on {
+                _ if self.options.lenient => return prev + 1,
+                Some(_) => panic!("{}already dropped: {}; dropped again on thread '{}'",
+                                  self.prefix(), self, current_thread_name()),
+                None => panic!("{}already dropped: {}", self.prefix(), self),
+            }
+        }
+        #[cfg(feature = "timing")]
+        {
+            *self.lifetime.lock().unwrap_or_else(PoisonError::into_inner) =
+                Some(self.created_at.elapsed());
+        }
+        if let Some(drop_seq) = drop_seq {
+            self.drop_seq.store(drop_seq, Ordering::SeqCst);
+        }
+        if self.created_on.is_some() {
+            *self.dropped_on.lock().unwrap_or_else(PoisonError::into_inner) =
+                Some(current_thread_name());
+        }
+
+        #[cfg(feature = "async")]
+        for waker in self.wakers.lock().unwrap_or_else(PoisonError::into_inner).drain(..) {
+            waker.wake();
+        }
+        1
+    }
+
+    /// Polls whether the token associated with this state has been dropped.
+    ///
+    /// Returns `Poll::Ready` once the token is dropped. Otherwise the waker in `cx` is woken when
+    /// it is, which lets an async test `.await` a token's drop:
+    ///
+    /// ```
+    /// # use std::{future::Future, pin::pin, sync::Arc, task::{Context, Poll, Wake}, thread::{self, Thread}};
+    /// # struct ThreadWaker(Thread);
+    /// # impl Wake for ThreadWaker {
+    /// #     fn wake(self: Arc<Self>) { self.0.unpark() }
+    /// # }
+    /// # fn block_on<F: Future>(f: F) -> F::Output {
+    /// #     let waker = Arc::new(ThreadWaker(thread::current())).into();
+    /// #     let mut cx = Context::from_waker(&waker);
+    /// #     let mut f = pin!(f);
+    /// #     loop {
+    /// #         match f.as_mut().poll(&mut cx) {
+    /// #             Poll::Ready(output) => return output,
+    /// #             Poll::Pending => thread::park(),
+    /// #         }
+    /// #     }
+    /// # }
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    /// let (token, state) = dropcheck.pair();
+    ///
+    /// thread::spawn(move || drop(token));
+    /// block_on(std::future::poll_fn(|cx| state.poll_dropped(cx)));
+    /// assert!(state.is_dropped());
+    /// ```
+    #[cfg(feature = "async")]
+    pub fn poll_dropped(&self, cx: &mut Context) -> Poll<()> {
+        if self.count.load(Ordering::SeqCst) > 0 {
+            return Poll::Ready(());
+        }
+
+        {
+            let mut wakers = self.wakers.lock().unwrap_or_else(PoisonError::into_inner);
+            if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+        }
+
+        // The token may have been dropped after the check above, but before the waker was
+        // registered.
+        if self.count.load(Ordering::SeqCst) > 0 {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// A weak handle to a `DropState`, which doesn't keep the state alive.
+///
+/// Created by `DropState::downgrade`.
+#[derive(Debug, Clone)]
+pub struct WeakDropState {
+    state: Weak<DropState>,
+}
+
+impl WeakDropState {
+    /// Returns the state, if it still exists.
+    ///
+    /// The set keeps the states of its tokens alive, so this only returns `None` once the set is
+    /// gone, along with any other strong references to the state.
+    pub fn upgrade(&self) -> Option<Arc<DropState>> {
+        self.state.upgrade()
+    }
+
+    /// Returns true if the state still exists and the set it belongs to does too.
+    ///
+    /// Lets an observer notice that the test it was watching has ended.
+    pub fn is_set_alive(&self) -> bool {
+        self.upgrade().is_some_and(|state| state.is_set_alive())
+    }
+}
+
+fn current_thread_name() -> String {
+    thread::current().name().unwrap_or("<unnamed>").to_owned()
+}
+
+/// Options that affect how tokens behave, fixed when each token is created.
+#[derive(Debug, Default, Clone, Copy)]
+struct Options {
+    thread_naming: bool,
+    lenient: bool,
+}
+
+/// A named predicate added by `DropCheck::add_invariant`.
+struct Invariant {
+    name: String,
+    predicate: Box<dyn Fn(&DropReport) -> bool + Send + Sync>,
+}
+
+impl fmt::Debug for Invariant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Invariant").field("name", &self.name).finish_non_exhaustive()
+    }
+}
+
+/// The shared part of a `DropCheck`, which its tokens refer to.
+#[derive(Debug, Default)]
+struct Set {
+    name: Option<String>,
+    states: RwLock<Vec<Arc<DropState>>>,
+    // States that aren't in `states` - removed ones, and those of unregistered tokens - whose
+    // tokens may still be dropped again, kept alive so that doing so is detected rather than a
+    // use-after-free.
+    retired: Mutex<Vec<Arc<DropState>>>,
+    // How many of the states are leaked, dropped at least once, and dropped more than once, kept
+    // up to date so that checking a clean set doesn't have to scan every state.
+    live: AtomicUsize,
+    dropped: AtomicUsize,
+    over_dropped: AtomicUsize,
+    next_id: AtomicU64,
+    next_drop_seq: AtomicU64,
+    /// One more than the drop sequence number at which to inject a panic, or zero for none.
+    inject_panic: AtomicU64,
+    thread_naming: AtomicBool,
+    lenient: AtomicBool,
+    watches: Mutex<Vec<DropWatch>>,
+    violations: Mutex<Vec<String>>,
+    invariants: Mutex<Vec<Invariant>>,
+    log: Mutex<Option<File>>,
+    log_timeline: AtomicBool,
+    #[cfg(feature = "async")]
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl Drop for Set {
+    fn drop(&mut self) {
+        // A token that outlives its set still releases its reference to its state when it's
+        // dropped, so the states of live tokens are leaked to outlive those drops.
+        let states = self.states.get_mut().unwrap_or_else(PoisonError::into_inner);
+        let retired = self.retired.get_mut().unwrap_or_else(PoisonError::into_inner);
+        for state in states.iter().chain(retired.iter()) {
+            if state.drop_count() < state.expected_drops()
+                && !state.leak_expected.load(Ordering::SeqCst)
+            {
+                mem::forget(Arc::clone(state));
+            }
+        }
+    }
+}
+
+impl Set {
+    fn options(&self) -> Options {
+        Options {
+            thread_naming: self.thread_naming.load(Ordering::SeqCst),
+            lenient: self.lenient.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Returns the prefix of the set's messages: its name and a colon, if it has a name.
+    fn prefix(&self) -> String {
+        self.name.as_ref().map(|name| format!("{}: ", name)).unwrap_or_default()
+    }
+
+    fn report(&self, states: &[Arc<DropState>]) -> DropReport {
+        let tokens = states.iter().map(|state| TokenReport::new(state)).collect();
+        let violations = self.violations.lock().unwrap_or_else(PoisonError::into_inner).clone();
+        DropReport::new(tokens, self.thread_naming.load(Ordering::SeqCst), self.name.clone(),
+                        violations)
+    }
+
+    /// Returns a report of `states` with the names of the invariants it fails added to its
+    /// violations.
+    fn checked_report(&self, states: &[Arc<DropState>]) -> DropReport {
+        let mut report = self.report(states);
+        for invariant in self.invariants.lock().unwrap_or_else(PoisonError::into_inner).iter() {
+            if !(invariant.predicate)(&report) {
+                report.add_violation(invariant.name.clone());
+            }
+        }
+        report
+    }
+
+    /// Records a violation of an invariant, unless the same one was already recorded.
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    fn record_violation(&self, violation: String) {
+        let mut violations = self.violations.lock().unwrap_or_else(PoisonError::into_inner);
+        if !violations.contains(&violation) {
+            self.log_violation(&violation);
+            violations.push(violation);
+        }
+    }
+
+    #[track_caller]
+    fn register(self: &Arc<Self>) -> Arc<DropState> {
+        let state = DropState::new(self.next_id.fetch_add(1, Ordering::SeqCst), self.options());
+        self.push(&mut self.states.write().unwrap_or_else(PoisonError::into_inner), state)
+    }
+
+    #[track_caller]
+    fn register_with_id(self: &Arc<Self>, id: u64) -> Arc<DropState> {
+        let state = DropState::new(id, self.options());
+        let mut states = self.states.write().unwrap_or_else(PoisonError::into_inner);
+        debug_assert!(states.iter().all(|state| state.id() != id), "duplicate token id {}", id);
+        self.push(&mut states, state)
+    }
+
+    /// Adds a new state to the set, binding any pending watches to it.
+    fn push(self: &Arc<Self>, states: &mut Vec<Arc<DropState>>, state: Arc<DropState>)
+        -> Arc<DropState>
+    {
+        *state.set.lock().unwrap_or_else(PoisonError::into_inner) = Arc::downgrade(self);
+        self.log_event(&format!("created {}", state));
+        #[cfg(feature = "metrics")]
+        metrics::counter!("dropcheck_tokens_created", self.metric_labels()).increment(1);
+        self.add_counts(&state);
+        states.push(Arc::clone(&state));
+        for watch in self.watches.lock().unwrap_or_else(PoisonError::into_inner).drain(..) {
+            *watch.state.lock().unwrap_or_else(PoisonError::into_inner) = Some(Arc::clone(&state));
+        }
+        state
+    }
+
+    /// Adds a state that was removed by `retain_live` or `reset_for_next_case` back to the set.
+    fn readmit(&self, state: &Arc<DropState>) {
+        let mut states = self.states.write().unwrap_or_else(PoisonError::into_inner);
+        self.add_counts(state);
+        states.push(Arc::clone(state));
+    }
+
+    /// Counts a state that's being added to the set.
+    fn add_counts(&self, state: &DropState) {
+        if state.is_leaked() {
+            self.live.fetch_add(1, Ordering::SeqCst);
+            #[cfg(feature = "metrics")]
+            self.publish_live();
+        }
+        if state.drop_count() > 0 {
+            self.dropped.fetch_add(1, Ordering::SeqCst);
+        }
+        if state.is_over_dropped() {
+            self.over_dropped.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Stops counting a state that's being removed from the set.
+    fn remove_counts(&self, state: &DropState) {
+        if state.is_leaked() {
+            self.live.fetch_sub(1, Ordering::SeqCst);
+            #[cfg(feature = "metrics")]
+            self.publish_live();
+        }
+        if state.drop_count() > 0 {
+            self.dropped.fetch_sub(1, Ordering::SeqCst);
+        }
+        if state.is_over_dropped() {
+            self.over_dropped.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Appends a line to the log file, if there is one.
+    ///
+    /// The file is unbuffered, so the line survives the process aborting right after.
+    fn log(&self, line: &str) {
+        if let Some(file) = &mut *self.log.lock().unwrap_or_else(PoisonError::into_inner) {
+            // There's nowhere to report a failure to write the log to.
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+
+    /// Logs a violation, counting it if metrics are enabled.
+    fn log_violation(&self, violation: &str) {
+        self.log(&format!("violation: {}", violation));
+        #[cfg(feature = "metrics")]
+        metrics::counter!("dropcheck_violations", self.metric_labels()).increment(1);
+    }
+
+    /// Logs a token's creation or drop, if the timeline is being logged.
+    fn log_event(&self, event: &str) {
+        if self.log_timeline.load(Ordering::SeqCst) {
+            self.log(event);
+        }
+    }
+
+    /// Labels the set's metrics with its name, if it has one.
+    #[cfg(feature = "metrics")]
+    fn metric_labels(&self) -> Vec<metrics::Label> {
+        self.name.iter().map(|name| metrics::Label::new("set", name.clone())).collect()
+    }
+
+    /// Sets the `dropcheck_tokens_alive` gauge to the number of live tokens.
+    #[cfg(feature = "metrics")]
+    fn publish_live(&self) {
+        let live = self.live.load(Ordering::SeqCst);
+        metrics::gauge!("dropcheck_tokens_alive", self.metric_labels()).set(live as f64);
+    }
+
+    /// Wakes every task waiting for the set to be fully dropped.
+    #[cfg(feature = "async")]
+    fn wake(&self) {
+        for waker in self.wakers.lock().unwrap_or_else(PoisonError::into_inner).drain(..) {
+            waker.wake();
+        }
+    }
+
+    fn summary(&self) -> SetSummary {
+        let states = self.states.read().unwrap_or_else(PoisonError::into_inner);
+        SetSummary {
+            created: states.len(),
+            dropped: states.iter().filter(|state| state.count.load(Ordering::SeqCst) > 0).count(),
+            leaked: states.iter().filter(|state| state.is_leaked()).count(),
+            over_dropped: states.iter().filter(|state| state.is_over_dropped()).count(),
+        }
+    }
+}
+
+/// A constraint on the order in which tokens are dropped, identified by their ids.
+///
+/// Every token a constraint refers to must have been dropped for the constraint to hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderConstraint {
+    /// The first token was dropped at some point before the second.
+    Before(u64, u64),
+
+    /// The first token was dropped immediately before the second.
+    Adjacent(u64, u64),
+
+    /// The token was the first to be dropped.
+    First(u64),
+
+    /// The token was the last to be dropped.
+    Last(u64),
+}
+
+impl OrderConstraint {
+    fn is_satisfied_by(&self, drop_order: &[u64]) -> bool {
+        let position = |id| drop_order.iter().position(|&dropped| dropped == id);
+        match *self {
+            OrderConstraint::Before(a, b) => match (position(a), position(b)) {
+                (Some(a), Some(b)) => a < b,
+                _ => false,
+            },
+            OrderConstraint::Adjacent(a, b) => match (position(a), position(b)) {
+                (Some(a), Some(b)) => a + 1 == b,
+                _ => false,
+            },
+            OrderConstraint::First(a) => drop_order.first() == Some(&a),
+            OrderConstraint::Last(a) => drop_order.last() == Some(&a),
+        }
+    }
+}
+
+impl fmt::Display for OrderConstraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrderConstraint::Before(a, b) => write!(f, "token #{} dropped before token #{}", a, b),
+            OrderConstraint::Adjacent(a, b) => write!(f, "token #{} dropped immediately before token #{}", a, b),
+            OrderConstraint::First(a) => write!(f, "token #{} dropped first", a),
+            OrderConstraint::Last(a) => write!(f, "token #{} dropped last", a),
+        }
+    }
+}
+
+/// A set of `DropToken`'s.
+#[derive(Debug, Default)]
+pub struct DropCheck {
+    set: Arc<Set>,
+    dump_on_drop: bool,
+    disarmed: AtomicBool,
+    registered_global: AtomicBool,
+}
+
+/// Formats a summary table of every token in the set.
+///
+/// ```
+/// # use dropcheck::DropCheck;
+/// let dropcheck = DropCheck::new();
+/// let (t0, t1) = (dropcheck.token(), dropcheck.token());
+/// drop(t1);
+///
+/// assert_eq!(dropcheck.to_string(),
+/// "      id  status
+///        0  not dropped
+///        1  dropped
+/// ");
+/// ```
+impl fmt::Display for DropCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.report(), f)
+    }
+}
+
+impl Drop for DropCheck {
+    fn drop(&mut self) {
+        if self.dump_on_drop {
+            eprintln!("{}", self);
+        }
+        if self.registered_global.load(Ordering::SeqCst) {
+            global::finish(&self.set);
+        }
+        if self.disarmed.load(Ordering::SeqCst) || thread::panicking() {
+            return;
+        }
+        self.assert_clean();
+    }
+}
+
+impl DropCheck {
+    /// Creates a new `DropCheck` set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new `DropCheck` set with a name, which prefixes every panic and report message
+    /// about the set.
+    ///
+    /// Tells the sets of a test apart, such as the source and destination containers of a move.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::named("left buffer");
+    /// std::mem::forget(dropcheck.token());
+    ///
+    /// let err = dropcheck.check().unwrap_err();
+    /// assert!(err.to_string().starts_with("left buffer: not all tokens dropped:\n    token #0"));
+    /// # dropcheck.disable_drop_assert();
+    /// ```
+    pub fn named(name: &str) -> Self {
+        let mut set = Set::default();
+        set.name = Some(name.to_owned());
+        Self {
+            set: Arc::new(set),
+            dump_on_drop: false,
+            disarmed: AtomicBool::new(false),
+            registered_global: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the name of this set, if it has one.
+    pub fn name(&self) -> Option<&str> {
+        self.set.name.as_deref()
+    }
+
+    /// Creates a set from existing states, rather than from tokens.
+    ///
+    /// Useful to test report and aggregation logic on states with chosen drop counts, created
+    /// with `DropState::with_count`. New tokens can still be created from the set as usual.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::{DropCheck, DropState};
+    /// let dropcheck = DropCheck::from_states(vec![
+    ///     DropState::with_count(0),
+    ///     DropState::with_count(1),
+    ///     DropState::with_count(2),
+    /// ]);
+    ///
+    /// let report = dropcheck.into_report();
+    /// assert_eq!(report.leaked().count(), 1);
+    /// assert_eq!(report.over_dropped().count(), 1);
+    /// ```
+    #[cfg(feature = "testing")]
+    pub fn from_states(states: Vec<Arc<DropState>>) -> Self {
+        let dropcheck = Self::new();
+        for state in &states {
+            dropcheck.set.add_counts(state);
+        }
+        *dropcheck.set.states.write().unwrap_or_else(PoisonError::into_inner) = states;
+        dropcheck
+    }
+
+    /// Records the names of the threads that create and drop each token in this set.
+    ///
+    /// Leak and double-drop panics then say which threads were involved, which helps debug
+    /// cross-thread ownership bugs:
+    ///
+    /// ```should_panic
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new().with_thread_naming();
+    /// let token = dropcheck.token();
+    ///
+    /// std::thread::Builder::new()
+    ///     .name("worker".into())
+    ///     .spawn(move || std::mem::forget(token))
+    ///     .unwrap().join().unwrap();
+    /// // panics with "not all tokens dropped:\n    token #0 created on thread 'main'"
+    /// ```
+    ///
+    /// The thread names are also part of each state's `Display` output:
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new().with_thread_naming();
+    /// let (token, state) = dropcheck.pair();
+    ///
+    /// std::thread::Builder::new()
+    ///     .name("worker".into())
+    ///     .spawn(move || drop(token))
+    ///     .unwrap().join().unwrap();
+    /// assert_eq!(state.to_string(), "token #0 created on thread 'main', dropped on thread 'worker'");
+    /// ```
+    pub fn with_thread_naming(self) -> Self {
+        self.set.thread_naming.store(true, Ordering::SeqCst);
+        self
+    }
+
+    /// Records tokens being dropped more than once, rather than panicking immediately.
+    ///
+    /// Over-dropped tokens are still reported by the final check when the set is dropped, as well
+    /// as by assertions like `assert_no_over_drops_during`. Being lenient lets a test run an
+    /// operation to completion and then inspect every over-drop it caused.
+    pub fn lenient(self) -> Self {
+        self.set.lenient.store(true, Ordering::SeqCst);
+        self
+    }
+
+    /// Writes every violation to the file at `path` as soon as it happens, and also every token's
+    /// creation and drop if `timeline` is true.
+    ///
+    /// A panic in drop glue during unwinding aborts the process, losing the panic messages that
+    /// would have explained it. The log file is written without buffering, so it still holds
+    /// everything up to the abort, which gives CI a usable report. The file is truncated first.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let path = std::env::temp_dir().join("dropcheck-log-to-file-doctest");
+    /// let dropcheck = DropCheck::new().lenient().log_to_file(&path, false).unwrap();
+    ///
+    /// let mut token = dropcheck.token();
+    /// unsafe {
+    ///     std::ptr::drop_in_place(&mut token);
+    ///     std::ptr::drop_in_place(&mut token);
+    /// }
+    /// std::mem::forget(token);
+    /// dropcheck.into_report();
+    ///
+    /// let log = std::fs::read_to_string(&path).unwrap();
+    /// assert_eq!(log, "violation: token #0 dropped again\n");
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn log_to_file(self, path: &Path, timeline: bool) -> io::Result<Self> {
+        *self.set.log.lock().unwrap_or_else(PoisonError::into_inner) = Some(File::create(path)?);
+        self.set.log_timeline.store(timeline, Ordering::SeqCst);
+        Ok(self)
+    }
+
+    /// Prints the summary table of this set to stderr when it's dropped, even if every token was
+    /// dropped correctly.
+    ///
+    /// Off by default. Useful to trace the final state of every set during a debugging session.
+    pub fn dump_on_drop(mut self, dump: bool) -> Self {
+        self.dump_on_drop = dump;
+        self
+    }
+
+    /// Stops the set from checking its tokens when it's dropped.
+    ///
+    /// `into_report` does the same, but consumes the set; this can be undone with
+    /// `enable_drop_assert` at any point before the set is dropped. Only the final check is
+    /// affected: double drops still panic unless the set is `lenient`, and explicit checks like
+    /// `check` still work.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    /// dropcheck.disable_drop_assert();
+    ///
+    /// std::mem::forget(dropcheck.token()); // leaking is part of this test
+    /// assert!(!dropcheck.all_dropped());
+    /// ```
+    pub fn disable_drop_assert(&self) {
+        self.disarmed.store(true, Ordering::SeqCst);
+    }
+
+    /// Re-enables the check made when the set is dropped, after `disable_drop_assert`.
+    pub fn enable_drop_assert(&self) {
+        self.disarmed.store(false, Ordering::SeqCst);
+    }
+
+    /// Returns a guard that checks this set when it's dropped, instead of the set checking itself
+    /// when it's dropped.
+    ///
+    /// Lets the set be long-lived or shared, while the check still happens at a chosen scope
+    /// boundary. Each guard checks the set once; `enable_drop_assert` restores the set's own check.
+    pub fn guard(&self) -> CheckGuard<'_> {
+        CheckGuard::new(self)
+    }
+
+    /// Starts a background thread that checks `invariant` against a report of this set every
+    /// `interval`, until the returned `Watchdog` is dropped.
+    ///
+    /// Each distinct violation the invariant returns is recorded, and fails the set's final
+    /// check, which suits soak tests that only look at the set at the end. The thread doesn't
+    /// keep the set alive.
+    ///
+    /// If `invariant` panics, the thread stops, and dropping the watchdog panics with the same
+    /// payload, unless the current thread is already panicking.
+    ///
+    /// # Examples
+    ///
+    /// ```should_panic
+    /// # use dropcheck::DropCheck;
+    /// use std::time::Duration;
+    ///
+    /// let dropcheck = DropCheck::new();
+    /// let watchdog = dropcheck.watchdog(Duration::from_millis(1), |report| {
+    ///     match report.tokens().iter().filter(|token| !token.is_dropped()).count() {
+    ///         live if live > 100 => Err(format!("{} tokens alive", live)),
+    ///         _ => Ok(()),
+    ///     }
+    /// });
+    ///
+    /// let v: Vec<_> = (0 .. 1000).map(|_| dropcheck.token()).collect();
+    /// std::thread::sleep(Duration::from_millis(100));
+    /// drop(v);
+    ///
+    /// drop(watchdog);
+    /// // panics with "invariant violated:\n    1000 tokens alive"
+    /// ```
+    ///
+    /// ```should_panic
+    /// # use dropcheck::DropCheck;
+    /// use std::time::Duration;
+    ///
+    /// let dropcheck = DropCheck::new();
+    /// let watchdog = dropcheck.watchdog(Duration::from_millis(1), |report| {
+    ///     assert!(report.tokens().len() < 10, "too many tokens");
+    ///     Ok(())
+    /// });
+    ///
+    /// let v: Vec<_> = (0 .. 10).map(|_| dropcheck.token()).collect();
+    /// std::thread::sleep(Duration::from_millis(100));
+    /// drop(v);
+    ///
+    /// drop(watchdog); // panics with "too many tokens"
+    /// ```
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn watchdog<F>(&self, interval: Duration, invariant: F) -> Watchdog
+        where F: Fn(&DropReport) -> Result<(), String> + Send + 'static
+    {
+        Watchdog::spawn(Arc::downgrade(&self.set), interval, invariant)
+    }
+
+    /// Adds an invariant, named `name`, that every check of this set must satisfy along with
+    /// having no leaked or over-dropped tokens.
+    ///
+    /// `predicate` is called with a report of the set by `check`, `finish`,
+    /// `reset_for_next_case` and the set's destructor, while the set is locked. If it returns
+    /// false, `name` is reported as a violated invariant.
+    ///
+    /// # Examples
+    ///
+    /// ```should_panic
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    /// dropcheck.add_invariant("even ids dropped before odd ids", |report| {
+    ///     let order = report.drop_order();
+    ///     let first_odd = order.iter().position(|id| id % 2 == 1).unwrap_or(order.len());
+    ///     order[first_odd..].iter().all(|id| id % 2 == 1)
+    /// });
+    ///
+    /// let (even, odd) = (dropcheck.token(), dropcheck.token());
+    /// drop(odd);
+    /// drop(even);
+    ///
+    /// // panics with "invariant violated:\n    even ids dropped before odd ids"
+    /// ```
+    pub fn add_invariant<F>(&self, name: &str, predicate: F)
+        where F: Fn(&DropReport) -> bool + Send + Sync + 'static
+    {
+        self.set.invariants.lock().unwrap_or_else(PoisonError::into_inner).push(Invariant {
+            name: name.to_owned(),
+            predicate: Box::new(predicate),
+        });
+    }
+
+    /// Installs this set as the thread's default, used by `DropToken::new`, until the returned
+    /// guard is dropped.
+    ///
+    /// Defaults nest: while the guard of an inner set is alive, that set is the default, and the
+    /// outer one is restored once it's dropped.
+    pub fn set_default(&self) -> DefaultGuard<'_> {
+        DefaultGuard::new(self)
+    }
+
+    /// Registers this set with the process-wide ledger summarized by `global_report`.
+    ///
+    /// Registering more than once has no further effect.
+    pub fn register_global(&self) {
+        if !self.registered_global.swap(true, Ordering::SeqCst) {
+            global::register(&self.set);
+        }
+    }
+
+    /// Creates a new `DropToken`, whose state is part of this set.
+    #[track_caller]
+    pub fn token(&self) -> DropToken {
+        DropToken::from_parts(self.set.register(), None, ())
+    }
+
+    /// Registers `value` in this set, so that it's checked as if it were a token.
+    ///
+    /// The value's `Tracker` gets a new token, which is dropped when the value is. If the value
+    /// was already tracked, its previous token is dropped now. See `TrackDrop`.
+    #[track_caller]
+    pub fn track<T: TrackDrop>(&self, value: &mut T) {
+        value.tracker().set(self.token());
+    }
+
+    /// Returns a builder for a token with any combination of options, like a chosen id, a name,
+    /// a payload and a panic on drop.
+    ///
+    /// See `TokenBuilder`.
+    pub fn build_token(&self) -> TokenBuilder<'_> {
+        TokenBuilder::new(self)
+    }
+
+    /// Returns a handle to the state of the next token created in this set.
+    ///
+    /// Useful when that token is created somewhere the caller can't get at its return value, like
+    /// deep inside a helper or macro that sets up a container.
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// fn setup(dropcheck: &DropCheck) -> Vec<dropcheck::DropToken> {
+    ///     vec![dropcheck.token()]
+    /// }
+    ///
+    /// let dropcheck = DropCheck::new();
+    /// let watch = dropcheck.watch_next();
+    ///
+    /// let mut v = setup(&dropcheck);
+    /// assert!(watch.is_not_dropped());
+    /// v.clear();
+    /// assert!(watch.is_dropped());
+    /// ```
+    pub fn watch_next(&self) -> DropWatch {
+        let watch = DropWatch::default();
+        self.set.watches.lock().unwrap_or_else(PoisonError::into_inner).push(watch.clone());
+        watch
+    }
+
+    /// Creates a new `DropToken` with a chosen id, whose state is part of this set.
+    ///
+    /// The id replaces the automatically assigned one everywhere ids are used, like reports and
+    /// `drop_order`, which is convenient when the tokens correspond to data that already has
+    /// stable keys. Choosing an id that's already used in the set is a bug, caught by a debug
+    /// assertion; note that automatically assigned ids keep counting up from zero regardless.
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    ///
+    /// let mut v = vec![dropcheck.token_with_id(100), dropcheck.token_with_id(200)];
+    /// v.reverse();
+    /// drop(v);
+    /// assert_eq!(dropcheck.drop_order(), vec![200, 100]);
+    /// ```
+    #[track_caller]
+    pub fn token_with_id(&self, id: u64) -> DropToken {
+        DropToken::from_parts(self.set.register_with_id(id), None, ())
+    }
+
+    /// Creates a new `DropToken` carrying `value` as its payload, whose state is part of this set.
+    ///
+    /// The token derefs to the payload, so it can stand in for a real element in a container.
+    /// Dropping the token drops the payload too.
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    ///
+    /// let mut v = vec![dropcheck.token_with_value(1), dropcheck.token_with_value(2)];
+    /// *v[0] += 10;
+    /// assert_eq!(v.iter().map(|token| **token).collect::<Vec<_>>(), vec![11, 2]);
+    /// ```
+    #[track_caller]
+    pub fn token_with_value<T>(&self, value: T) -> DropToken<T> {
+        DropToken::from_parts(self.set.register(), None, value)
+    }
+
+    /// Creates a new `DropToken` carrying `value` as its payload, named after the value's `Debug`
+    /// representation.
+    ///
+    /// The name is part of the token's description, so leak and double-drop reports show the
+    /// element itself rather than just an id:
+    ///
+    /// ```should_panic
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    ///
+    /// let v = vec![dropcheck.token_with_named_value(String::from("hello"))];
+    /// std::mem::forget(v);
+    /// // panics with "not all tokens dropped:\n    token #0 (\"hello\")"
+    /// ```
+    #[track_caller]
+    pub fn token_with_named_value<T: fmt::Debug>(&self, value: T) -> DropToken<T> {
+        let token = self.token_with_value(value);
+        let _ = token.state.name.set(format!("{:?}", *token));
+        token
+    }
+
+    /// Creates a new `DropToken` whose payload is the only strong reference to `value`, along
+    /// with a weak reference to it.
+    ///
+    /// Dropping the token drops `value`, unless something else has taken a strong reference in
+    /// the meantime. Use this to check that containers holding `Weak`s, like caches and observer
+    /// lists, don't keep their pointees alive:
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    ///
+    /// let (token, weak) = dropcheck.weak_payload_token(String::from("hello"));
+    /// let observers = vec![std::sync::Arc::downgrade(&token)];
+    ///
+    /// assert_eq!(weak.upgrade().as_deref().map(String::as_str), Some("hello"));
+    /// drop(token);
+    /// assert!(weak.upgrade().is_none());
+    /// assert!(observers[0].upgrade().is_none());
+    /// ```
+    #[track_caller]
+    pub fn weak_payload_token<T>(&self, value: T) -> (DropToken<Arc<T>>, Weak<T>) {
+        let value = Arc::new(value);
+        let weak = Arc::downgrade(&value);
+        (self.token_with_value(value), weak)
+    }
+
+    /// Creates a new `DropToken` that panics when it's dropped.
+    ///
+    /// The token's state is marked as dropped before the panic, so it isn't leaked. Use this to
+    /// inject a panic into a container's drop path, and check that the container still drops its
+    /// other elements. The panic is expected, so it doesn't count for `any_drop_panicked`.
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    ///
+    /// let v = vec![dropcheck.token(), dropcheck.panicking_token(), dropcheck.token()];
+    /// assert!(std::panic::catch_unwind(move || drop(v)).is_err());
+    ///
+    /// assert!(dropcheck.all_dropped()); // Vec keeps dropping elements after one panics
+    /// assert!(!dropcheck.any_drop_panicked());
+    /// ```
+    #[track_caller]
+    pub fn panicking_token(&self) -> DropToken {
+        let token = self.token();
+        token.state.panic_expected.store(true, Ordering::SeqCst);
+        token
+    }
+
+    /// Creates a new `BorrowToken` that borrows `target`.
+    ///
+    /// See `BorrowToken`.
+    #[track_caller]
+    pub fn borrow_token<'a>(&self, target: &'a BorrowTarget) -> BorrowToken<'a> {
+        BorrowToken::new(self.token(), target)
+    }
+
+    /// Moves `value` into a new `CheckedBox` tracked by this set.
+    ///
+    /// See `CheckedBox`.
+    #[track_caller]
+    pub fn checked_box<T>(&self, value: T) -> CheckedBox<T> {
+        CheckedBox::new(self.token_with_value(Box::new(value)))
+    }
+
+    /// Creates a new `ProbeToken`, whose auto traits are chosen by the marker `M`.
+    ///
+    /// See `ProbeToken`.
+    #[track_caller]
+    pub fn probe_token<M>(&self) -> ProbeToken<M> {
+        ProbeToken::new(self.token())
+    }
+
+    /// Moves a token from the set it belongs to into this one, and returns it.
+    ///
+    /// The token's state is removed from its original set, so only this set checks it from now
+    /// on. It gets a new id from this set, like a freshly created token; anything that holds its
+    /// state sees the new id too. Lets a fixture that owns its own `DropCheck` hand tokens over
+    /// to the caller.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::{DropCheck, DropToken};
+    /// fn fixture(caller: &DropCheck) -> Vec<DropToken> {
+    ///     let dropcheck = DropCheck::new();
+    ///     let v = vec![dropcheck.token(), dropcheck.token()];
+    ///     drop(dropcheck.token());
+    ///
+    ///     // the fixture's own set only checks the token it dropped
+    ///     caller.adopt_all(v)
+    /// }
+    ///
+    /// let dropcheck = DropCheck::new();
+    /// let v = fixture(&dropcheck);
+    /// assert_eq!(v[0].id(), 0);
+    /// assert!(dropcheck.none_dropped());
+    /// drop(v);
+    /// assert!(dropcheck.all_dropped());
+    /// ```
+    pub fn adopt<T>(&self, token: DropToken<T>) -> DropToken<T> {
+        if let Some(original) = token.state.set() {
+            if Arc::ptr_eq(&original, &self.set) {
+                return token;
+            }
+            if !token.state.retired.swap(false, Ordering::SeqCst) {
+                original.states.write().unwrap_or_else(PoisonError::into_inner)
+                        .retain(|state| !Arc::ptr_eq(state, &token.state));
+                original.remove_counts(&token.state);
+            }
+        }
+
+        let mut states = self.set.states.write().unwrap_or_else(PoisonError::into_inner);
+        token.state.id.store(self.set.next_id.fetch_add(1, Ordering::SeqCst), Ordering::SeqCst);
+        self.set.add_counts(&token.state);
+        states.push(Arc::clone(&token.state));
+        *token.state.set.lock().unwrap_or_else(PoisonError::into_inner) = Arc::downgrade(&self.set);
+        token
+    }
+
+    /// Moves every token in `tokens` into this set, as with `adopt`.
+    pub fn adopt_all<T, I>(&self, tokens: I) -> Vec<DropToken<T>>
+        where I: IntoIterator<Item = DropToken<T>>
+    {
+        tokens.into_iter().map(|token| self.adopt(token)).collect()
+    }
+
+    /// Moves the states of the live tokens that match `predicate` into a new, independent set.
+    ///
+    /// The moved tokens are checked by the new set from then on, and no longer by this one; they
+    /// keep their ids. The new set has the same options as this one, like thread naming. Useful
+    /// to hand part of the outstanding tokens over to another phase of a test, or another thread,
+    /// with its own final check.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    /// let first: Vec<_> = (0 .. 3).map(|_| dropcheck.token()).collect();
+    /// let second: Vec<_> = (0 .. 3).map(|_| dropcheck.token()).collect();
+    ///
+    /// let ids: Vec<u64> = second.iter().map(|token| token.id()).collect();
+    /// let second_check = dropcheck.split_off(|state| ids.contains(&state.id()));
+    /// std::thread::spawn(move || {
+    ///     drop(second);
+    ///     second_check.finish().unwrap();
+    /// }).join().unwrap();
+    ///
+    /// drop(first);
+    /// ```
+    ///
+    /// If `predicate` panics, no tokens are moved, and this set keeps working as usual once the
+    /// panic is caught:
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// use std::panic::{self, AssertUnwindSafe};
+    ///
+    /// let dropcheck = DropCheck::new();
+    /// let token = dropcheck.token();
+    ///
+    /// let result = panic::catch_unwind(AssertUnwindSafe(|| {
+    ///     dropcheck.split_off(|_| panic!("bad predicate"))
+    /// }));
+    /// assert!(result.is_err());
+    ///
+    /// drop(token);
+    /// dropcheck.check().unwrap();
+    /// ```
+    ///
+    /// That includes states the predicate accepted before panicking:
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// use std::panic::{self, AssertUnwindSafe};
+    ///
+    /// let dropcheck = DropCheck::new();
+    /// let first = dropcheck.token();
+    /// let second = dropcheck.token();
+    ///
+    /// let result = panic::catch_unwind(AssertUnwindSafe(|| {
+    ///     dropcheck.split_off(|state| if state.id() == 0 { true } else { panic!("bad predicate") })
+    /// }));
+    /// assert!(result.is_err());
+    ///
+    /// std::mem::forget(first);
+    /// drop(second);
+    /// assert!(dropcheck.check().is_err());
+    /// # dropcheck.disable_drop_assert();
+    /// ```
+    pub fn split_off<F>(&self, mut predicate: F) -> DropCheck
+        where F: FnMut(&DropState) -> bool
+    {
+        let other = DropCheck::new();
+        let options = self.set.options();
+        other.set.thread_naming.store(options.thread_naming, Ordering::SeqCst);
+        other.set.lenient.store(options.lenient, Ordering::SeqCst);
+
+        let mut states = self.set.states.write().unwrap_or_else(PoisonError::into_inner);
+
+        // The predicate is run on every state before any are moved, so that a panic in it leaves
+        // both sets untouched.
+        let selected: Vec<bool> = states.iter()
+                                        .map(|state| state.drop_count() == 0 && predicate(state))
+                                        .collect();
+
+        let mut moved = other.set.states.write().unwrap_or_else(PoisonError::into_inner);
+        let mut selected = selected.into_iter();
+        states.retain(|state| {
+            if selected.next().unwrap_or(false) {
+                *state.set.lock().unwrap_or_else(PoisonError::into_inner) =
+                    Arc::downgrade(&other.set);
+                self.set.remove_counts(state);
+                other.set.add_counts(state);
+                moved.push(Arc::clone(state));
+                false
+            } else {
+                true
+            }
+        });
+        drop(moved);
+        drop(states);
+        other
+    }
+
+    /// Creates a new `DropToken` that must not be dropped until `passed` is set.
+    ///
+    /// Use this to check that a value is dropped after a synchronization point, such as a
+    /// `std::sync::Barrier`, rather than before it. The protocol is manual: the thread that
+    /// releases the others sets `passed` to true just before its own call to `wait`. Dropping the
+    /// token while `passed` is still false panics, and the panic is recorded like any other
+    /// unexpected drop panic. Consuming it too early with `DropToken::into_inner` panics too.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// use std::sync::{Arc, Barrier, atomic::{AtomicBool, Ordering}};
+    /// use std::thread;
+    ///
+    /// let dropcheck = DropCheck::new();
+    /// let barrier = Arc::new(Barrier::new(2));
+    /// let passed = Arc::new(AtomicBool::new(false));
+    /// let token = dropcheck.barrier_token(Arc::clone(&passed));
+    ///
+    /// let worker = {
+    ///     let barrier = Arc::clone(&barrier);
+    ///     thread::spawn(move || {
+    ///         barrier.wait();
+    ///         drop(token);
+    ///     })
+    /// };
+    ///
+    /// passed.store(true, Ordering::SeqCst);
+    /// barrier.wait();
+    /// worker.join().unwrap();
+    /// assert!(dropcheck.all_dropped());
+    /// ```
+    ///
+    /// Dropping the token too early:
+    ///
+    /// ```should_panic
+    /// # use dropcheck::DropCheck;
+    /// # use std::sync::{Arc, atomic::AtomicBool};
+    /// let dropcheck = DropCheck::new();
+    /// let token = dropcheck.barrier_token(Arc::new(AtomicBool::new(false)));
+    ///
+    /// drop(token); // panics with "token #0 dropped before its barrier was passed"
+    /// ```
+    ///
+    /// ```should_panic
+    /// # use dropcheck::DropCheck;
+    /// # use std::sync::{Arc, atomic::AtomicBool};
+    /// let dropcheck = DropCheck::new();
+    /// let token = dropcheck.barrier_token(Arc::new(AtomicBool::new(false)));
+    ///
+    /// token.into_inner(); // panics too
+    /// ```
+    #[track_caller]
+    pub fn barrier_token(&self, passed: Arc<AtomicBool>) -> DropToken {
+        let token = self.token();
+        *token.state.barrier.lock().unwrap_or_else(PoisonError::into_inner) = Some(passed);
+        token
+    }
+
+    /// Creates a new `DropToken` that isn't part of this set.
+    ///
+    /// Creating one doesn't take the lock on the set's states, so it isolates the cost of the
+    /// token's own state in benchmarks. The set keeps the state alive until the set is dropped,
+    /// so that dropping the token twice still panics. However, the token is invisible to
+    /// `all_dropped`, `none_dropped`, `drop_order` and every other query on the set, and leaking
+    /// it goes undetected.
+    ///
+    /// Clones of the token are unregistered too. As they aren't tied to any set, nothing else can
+    /// keep their state alive, so it's leaked to keep double drops of the clone detectable.
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    ///
+    /// let token = dropcheck.unregistered_token();
+    /// std::mem::forget(token);
+    /// assert!(dropcheck.all_dropped());
+    /// ```
+    ///
+    /// ```should_panic
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    /// let mut token = dropcheck.unregistered_token();
+    ///
+    /// unsafe {
+    ///     std::ptr::drop_in_place(&mut token);
+    ///     std::ptr::drop_in_place(&mut token); // panics
+    /// }
+    /// ```
+    #[track_caller]
+    pub fn unregistered_token(&self) -> DropToken {
+        let state = DropState::new(u64::MAX, self.set.options());
+        self.set.retired.lock().unwrap_or_else(PoisonError::into_inner).push(Arc::clone(&state));
+        DropToken::from_parts(state, None, ())
+    }
+
+    /// Creates a new `DropToken` for a fresh slot, along with the slot's `Generation`.
+    ///
+    /// See `Generation` for the generation-matching contract.
+    ///
+    /// # Examples
+    ///
+    /// A slot that's reused correctly:
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    ///
+    /// let (token, generation) = dropcheck.generational_token();
+    /// drop(token);
+    ///
+    /// generation.advance();
+    /// let token = dropcheck.generational_token_for(&generation);
+    /// drop(token);
+    /// ```
+    ///
+    /// A stale token being dropped after its slot was reused:
+    ///
+    /// ```should_panic
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    ///
+    /// let (stale, generation) = dropcheck.generational_token();
+    /// generation.advance();
+    /// let token = dropcheck.generational_token_for(&generation);
+    ///
+    /// drop(stale); // panics with "ABA: generation mismatch"
+    /// ```
+    #[track_caller]
+    pub fn generational_token(&self) -> (DropToken, Generation) {
+        let generation = Generation::default();
+        (self.generational_token_for(&generation), generation)
+    }
+
+    /// Creates a new `DropToken` for the current generation of an existing slot.
+    #[track_caller]
+    pub fn generational_token_for(&self, generation: &Generation) -> DropToken {
+        let generation = Some((generation.clone(), generation.get()));
+        DropToken::from_parts(self.set.register(), generation, ())
+    }
+
+    /// Creates a new `DropToken`, and also gives you a handle to the state.
+    ///
+    /// # Examples
+    ///
+    /// Checking when an operation drops a value:
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    ///
+    /// let mut v = vec![dropcheck.token(); 10];
+    ///
+    /// let (t1, s1) = dropcheck.pair();
     /// v.push(t1);
     ///
-    /// assert!(s1.is_not_dropped());
-    /// v.pop();
-    /// assert!(s1.is_dropped()); // vec drops items immediately
+    /// assert!(s1.is_not_dropped());
+    /// v.pop();
+    /// assert!(s1.is_dropped()); // vec drops items immediately
+    /// ```
+    #[track_caller]
+    pub fn pair(&self) -> (DropToken, Arc<DropState>) {
+        let state = self.set.register();
+
+        (DropToken::from_parts(Arc::clone(&state), None, ()), state)
+    }
+
+    /// Returns the number of tokens in this set, whether or not they've been dropped.
+    pub fn len(&self) -> usize {
+        self.set.states.read().unwrap_or_else(PoisonError::into_inner).len()
+    }
+
+    /// Returns true if no token has been created in this set.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the state of the token at `index` in this set, if there is one.
+    ///
+    /// Tokens are indexed in the order they were added to the set: creation order, followed by
+    /// tokens moved in with `adopt`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// use std::collections::VecDeque;
+    ///
+    /// let dropcheck = DropCheck::new();
+    /// let mut queue: VecDeque<_> = (0 .. 10).map(|_| dropcheck.token()).collect();
+    /// assert_eq!(dropcheck.len(), 10);
+    ///
+    /// queue.pop_front();
+    /// assert!(dropcheck.state(0).unwrap().is_dropped());
+    /// assert!(dropcheck.state(1).unwrap().is_not_dropped());
+    /// assert!(dropcheck.state(10).is_none());
+    /// ```
+    pub fn state(&self, index: usize) -> Option<Arc<DropState>> {
+        self.set.states.read().unwrap_or_else(PoisonError::into_inner).get(index).cloned()
+    }
+
+    /// Returns true if none of the `Token`s in this set have been dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let set = DropCheck::new();
+    /// assert!(set.none_dropped()); // an empty set has no dropped tokens
+    ///
+    /// let t1 = set.token();
+    /// assert!(set.none_dropped());
+    ///
+    /// let t2 = set.token();
+    /// assert!(set.none_dropped());
+    ///
+    /// drop(t1);
+    /// assert!(!set.none_dropped());
+    /// ```
+    pub fn none_dropped(&self) -> bool {
+        self.set.dropped.load(Ordering::SeqCst) == 0
+    }
+
+    /// Returns true if all of the `Token`s have been dropped.
+    ///
+    /// Tokens leaked with `DropToken::leak_intentionally` are ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let set = DropCheck::new();
+    /// assert!(set.all_dropped()); // all of the tokens in an empty set have been dropped
+    ///
+    /// let mut v = vec![];
+    /// for _ in 0 .. 100 {
+    ///     v.push(set.token());
+    /// }
+    /// assert!(!set.all_dropped());
+    ///
+    /// drop(v);
+    /// assert!(set.all_dropped()); // vec has dropped every token in it
+    /// ```
+    pub fn all_dropped(&self) -> bool {
+        self.set.live.load(Ordering::SeqCst) == 0
+    }
+
+    /// Counts the tokens in this set by status, in a single pass.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    /// let t0 = dropcheck.token();
+    /// drop(dropcheck.token());
+    /// dropcheck.token().leak_intentionally();
+    ///
+    /// let counts = dropcheck.status_counts();
+    /// assert_eq!(counts.to_string(),
+    ///            "1 live, 1 dropped, 0 over-dropped, 0 disarmed, 1 leaked on purpose");
+    /// # drop(t0);
+    /// ```
+    ///
+    /// Once the set's final check is disabled, tokens that haven't been dropped are counted as
+    /// disarmed rather than live, as leaking them will go unreported:
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    /// std::mem::forget(dropcheck.token());
+    /// assert_eq!(dropcheck.status_counts().live(), 1);
+    ///
+    /// dropcheck.disable_drop_assert();
+    /// let counts = dropcheck.status_counts();
+    /// assert_eq!((counts.live(), counts.disarmed()), (0, 1));
+    /// ```
+    pub fn status_counts(&self) -> StatusCounts {
+        let disarmed = self.disarmed.load(Ordering::SeqCst);
+        let mut counts = StatusCounts::default();
+        for state in self.set.states.read().unwrap_or_else(PoisonError::into_inner).iter() {
+            match state.drop_count() {
+                0 if state.leak_expected.load(Ordering::SeqCst) => counts.expected_leak += 1,
+                x if x < state.expected_drops() && disarmed => counts.disarmed += 1,
+                x if x < state.expected_drops() => counts.live += 1,
+                x if x == state.expected_drops() => counts.dropped += 1,
+                _ => counts.over_dropped += 1,
+            }
+        }
+        counts
+    }
+
+    /// Panics with the problems `check` finds, if any, after logging them and making them
+    /// available to the panic hook.
+    fn assert_clean(&self) {
+        if let Err(err) = self.check() {
+            let msg = err.to_string();
+            self.set.log_violation(&msg);
+            hook::set_pending(&msg, err.report().clone());
+            panic!("{}", msg);
+        }
+    }
+
+    /// Returns a snapshot of every token in this set.
+    pub fn report(&self) -> DropReport {
+        self.set.report(&self.set.states.read().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Consumes the set, returning a snapshot of every token in it instead of checking them.
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    /// std::mem::forget(dropcheck.token());
+    ///
+    /// let report = dropcheck.into_report(); // doesn't panic
+    /// assert_eq!(report.leaked().count(), 1);
+    /// ```
+    pub fn into_report(self) -> DropReport {
+        self.disarmed.store(true, Ordering::SeqCst);
+        self.report()
+    }
+
+    /// Compares the tokens leaked or dropped more than once in this set with the ones from a
+    /// previous run, stored in the baseline file at `path`, then replaces the baseline.
+    ///
+    /// Turns chasing intermittent leaks into a regression workflow: each run reports which tokens
+    /// started or stopped failing since the last one. See `DropReport::compare_with_baseline`.
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// # let path = std::env::temp_dir().join(format!("dropcheck-baseline-{}", std::process::id()));
+    /// for leak in [true, false] {
+    ///     let dropcheck = DropCheck::new();
+    ///     let (t0, t1) = (dropcheck.token(), dropcheck.token());
+    ///     drop(t0);
+    ///     if leak {
+    ///         std::mem::forget(t1);
+    ///     } else {
+    ///         drop(t1);
+    ///     }
+    ///
+    ///     let diff = dropcheck.compare_with_baseline(&path).unwrap();
+    ///     if leak {
+    ///         assert_eq!(diff.newly_failing(), &[1]);
+    ///         dropcheck.into_report();
+    ///     } else {
+    ///         assert_eq!(diff.newly_fixed(), &[1]);
+    ///     }
+    /// }
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn compare_with_baseline(&self, path: &Path) -> io::Result<BaselineDiff> {
+        self.report().compare_with_baseline(path)
+    }
+
+    /// Checks that no token in this set was leaked or dropped more than once, without panicking.
+    ///
+    /// Takes constant time if the set is clean; only failures need every token to be reported.
+    pub fn check(&self) -> Result<(), DropError> {
+        if self.set.live.load(Ordering::SeqCst) == 0
+            && self.set.over_dropped.load(Ordering::SeqCst) == 0
+            && self.set.violations.lock().unwrap_or_else(PoisonError::into_inner).is_empty()
+            && self.set.invariants.lock().unwrap_or_else(PoisonError::into_inner).is_empty()
+        {
+            return Ok(());
+        }
+        self.set.checked_report(&self.set.states.read().unwrap_or_else(PoisonError::into_inner))
+            .into_result()
+    }
+
+    /// Consumes the set, checking it like its destructor would, but returning the error instead
+    /// of panicking.
+    ///
+    /// Lets drop-checking participate in tests that return a `Result`:
+    ///
+    /// ```
+    /// # use dropcheck::{DropCheck, DropError};
+    /// fn test_vec() -> Result<(), DropError> {
+    ///     let dropcheck = DropCheck::new();
+    ///     let v = vec![dropcheck.token(); 10];
+    ///     drop(v);
+    ///
+    ///     dropcheck.finish()
+    /// }
+    /// # test_vec().unwrap();
+    /// ```
+    pub fn finish(self) -> Result<(), DropError> {
+        self.disarmed.store(true, Ordering::SeqCst);
+        self.check()
+    }
+
+    /// Asserts that the tokens of exactly the `expected` states have been dropped, and that every
+    /// other token in this set is still alive.
+    ///
+    /// # Panics
+    ///
+    /// Panics listing the tokens that were dropped but shouldn't have been, and the ones that
+    /// should have been dropped but weren't.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    /// let (mut v, states): (Vec<_>, Vec<_>) = (0 .. 5).map(|_| dropcheck.pair()).unzip();
+    ///
+    /// v.truncate(3);
+    /// dropcheck.assert_dropped_exactly(&[&states[3], &states[4]]);
+    /// ```
+    #[track_caller]
+    pub fn assert_dropped_exactly(&self, expected: &[&Arc<DropState>]) {
+        let mut unexpected = String::new();
+        let mut missing = String::new();
+        for state in self.set.states.read().unwrap_or_else(PoisonError::into_inner).iter() {
+            let is_expected = expected.iter().any(|expected| Arc::ptr_eq(expected, state));
+            match (state.drop_count() > 0, is_expected) {
+                (true, false) => unexpected.push_str(&format!("\n    {}", state)),
+                (false, true) => missing.push_str(&format!("\n    {}", state)),
+                _ => {},
+            }
+        }
+        if !unexpected.is_empty() || !missing.is_empty() {
+            let mut msg = format!("{}dropped tokens differ from the expected ones:", self.set.prefix());
+            if !unexpected.is_empty() {
+                msg.push_str(&format!("\n  dropped, but expected alive:{}", unexpected));
+            }
+            if !missing.is_empty() {
+                msg.push_str(&format!("\n  alive, but expected dropped:{}", missing));
+            }
+            panic!("{}", msg);
+        }
+    }
+
+    /// Asserts that every token in this set was dropped exactly once.
+    ///
+    /// This is the strictest check of all: `all_dropped` is satisfied by tokens dropped more than
+    /// once, whereas this reports every token whose `drop_count` isn't exactly one. Only tokens
+    /// leaked with `DropToken::leak_intentionally` are exempt.
+    ///
+    /// # Panics
+    ///
+    /// Panics with every token that wasn't dropped exactly once.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    /// let v = vec![dropcheck.token(); 10];
+    ///
+    /// drop(v);
+    /// dropcheck.assert_each_dropped_once();
+    /// ```
+    #[track_caller]
+    pub fn assert_each_dropped_once(&self) {
+        let mut msg = String::new();
+        for state in self.set.states.read().unwrap_or_else(PoisonError::into_inner).iter() {
+            let count = state.drop_count();
+            if count != state.expected_drops()
+                && !(count == 0 && state.leak_expected.load(Ordering::SeqCst))
+            {
+                msg.push_str(&format!("\n    {} dropped {} times", state, count));
+            }
+        }
+        if !msg.is_empty() {
+            panic!("{}tokens not dropped exactly once:{}", self.set.prefix(), msg);
+        }
+    }
+
+    /// Asserts that every token acquired from this set has been released.
+    ///
+    /// This is `all_dropped` framed for acquire/release pairs, like lock guards: a token is
+    /// created on acquisition and dropped on release, so the two counts must match once the test
+    /// is done. Every clone of a token is a handle of its own, so it counts as an acquisition too,
+    /// which makes this suitable for reference-counted containers: each handle must be released
+    /// exactly once, and a handle released twice can't make up for one that's still held. Tokens
+    /// leaked with `DropToken::leak_intentionally` aren't counted as held.
+    ///
+    /// # Panics
+    ///
+    /// Panics with the number of acquisitions and releases, and the tokens that are still held or
+    /// were released more than once.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    /// let handle = dropcheck.token();
+    /// let clones = vec![handle.clone(), handle.clone()];
+    ///
+    /// drop(handle);
+    /// drop(clones);
+    /// dropcheck.assert_balanced();
+    /// ```
+    ///
+    /// ```should_panic
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    /// let _guard = dropcheck.token();
+    /// drop(dropcheck.token());
+    ///
+    /// dropcheck.assert_balanced(); // panics: 2 acquired, 1 released
     /// ```
-    pub fn pair(&self) -> (DropToken, Arc<DropState>) {
-        let state = DropState::new();
-        self.push(Arc::clone(&state));
+    #[track_caller]
+    pub fn assert_balanced(&self) {
+        let states = self.set.states.read().unwrap_or_else(PoisonError::into_inner);
+        let mut unbalanced = states.iter()
+            .filter(|state| state.is_leaked() || state.is_over_dropped())
+            .peekable();
+        if unbalanced.peek().is_some() {
+            let acquired = states.iter()
+                .filter(|state| !state.leak_expected.load(Ordering::SeqCst))
+                .count();
+            let released: usize = states.iter().map(|state| state.drop_count()).sum();
+            let mut msg = format!("{}unbalanced: {} acquired, {} released:",
+                                  self.set.prefix(), acquired, released);
+            for state in unbalanced {
+                match state.drop_count() {
+                    0 => msg.push_str(&format!("\n    {} still held", state)),
+                    x => msg.push_str(&format!("\n    {} released {} times", state, x)),
+                }
+            }
+            panic!("{}", msg);
+        }
+    }
+
+    /// Asserts that no token was leaked, dropped more than once, or panicked unexpectedly while
+    /// being dropped.
+    ///
+    /// The strictest check of a container's drop behaviour, combining `check` and
+    /// `any_drop_panicked`. Drop order isn't covered, since the set has no expected order; check
+    /// it separately with `assert_fifo`, `assert_lifo` or `assert_drop_order_matches_pattern`.
+    ///
+    /// # Panics
+    ///
+    /// Panics with a single report listing the failing tokens in every category.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    ///
+    /// let v = vec![dropcheck.token(), dropcheck.panicking_token(), dropcheck.token()];
+    /// assert!(std::panic::catch_unwind(move || drop(v)).is_err());
+    ///
+    /// dropcheck.assert_fully_correct(); // the panic was expected
+    /// ```
+    #[track_caller]
+    pub fn assert_fully_correct(&self) {
+        let mut msg = match self.check() {
+            Ok(()) => String::new(),
+            Err(err) => err.to_string(),
+        };
+
+        let states = self.set.states.read().unwrap_or_else(PoisonError::into_inner);
+        let mut panicked = states.iter().filter(|state| {
+            state.drop_panicked.load(Ordering::SeqCst) && !state.panic_expected.load(Ordering::SeqCst)
+        }).peekable();
+        if panicked.peek().is_some() {
+            if msg.is_empty() {
+                msg = self.set.prefix();
+            } else {
+                msg.push('\n');
+            }
+            msg.push_str("tokens whose drop panicked:");
+            for state in panicked {
+                msg.push_str(&format!("\n    {}", state));
+            }
+        }
+        drop(states);
 
-        (DropToken {
-            set: Arc::downgrade(&self.set),
-            state: Arc::clone(&state),
-        }, state)
+        if !msg.is_empty() {
+            panic!("{}", msg);
+        }
     }
 
-    /// Returns true if none of the `Token`s in this set have been dropped.
+    /// Runs `f`, which is expected to panic, and asserts that no token was leaked or dropped more
+    /// than once despite the panic.
+    ///
+    /// If the set is clean, the panic from `f` is resumed. Otherwise, this panics with a report
+    /// that includes both the problems found and the message of the original panic. Nothing is
+    /// required of `f` if it doesn't panic, other than leaving the set clean.
+    ///
+    /// # Examples
+    ///
+    /// ```should_panic
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    ///
+    /// dropcheck.assert_clean_on_unwind(|| {
+    ///     let v = vec![dropcheck.token(), dropcheck.token()];
+    ///     panic!("operation failed halfway through"); // v is dropped during unwinding
+    /// });
+    /// // resumes the "operation failed halfway through" panic
+    /// ```
+    ///
+    /// An operation that leaks when it panics:
+    ///
+    /// ```should_panic
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    ///
+    /// dropcheck.assert_clean_on_unwind(|| {
+    ///     let v = std::mem::ManuallyDrop::new(vec![dropcheck.token(), dropcheck.token()]);
+    ///     panic!("operation failed halfway through");
+    /// });
+    /// // panics with "not all tokens dropped: ... after panic: operation failed halfway through"
+    /// ```
+    #[track_caller]
+    pub fn assert_clean_on_unwind<F>(&self, f: F)
+        where F: FnOnce() + UnwindSafe
+    {
+        let result = panic::catch_unwind(f);
+        match (result, self.check()) {
+            (Ok(()), Ok(())) => {},
+            (Err(payload), Ok(())) => panic::resume_unwind(payload),
+            (Ok(()), Err(err)) => panic!("{}", err),
+            (Err(payload), Err(err)) => {
+                panic!("{}\nafter panic: {}", err, panic_message(&*payload))
+            },
+        }
+    }
+
+    /// Removes the states of dropped tokens from this set, keeping the live ones.
+    ///
+    /// Long-running tests that create many short-lived tokens can call this periodically to keep
+    /// the cost of the set's queries and checks proportional to the number of live tokens. Once
+    /// removed, a state no longer affects any query on the set:
+    ///
+    /// * `none_dropped` returns true until another token is dropped.
+    /// * `all_dropped` is unaffected, as it only depends on live tokens.
+    /// * `drop_order` and the summary table only include tokens dropped since.
+    /// * The final check when the set is dropped only covers the remaining tokens.
+    ///
+    /// The states of tokens that were dropped more than once are kept, so the final check still
+    /// reports them. So are the states of tokens that are dropped again after being removed,
+    /// which are added back to the set. To detect that, removed states stay allocated until the
+    /// set is dropped. Ids are never reused.
     ///
     /// # Examples
     ///
     /// ```
     /// # use dropcheck::DropCheck;
-    /// let set = DropCheck::new();
-    /// assert!(set.none_dropped()); // an empty set has no dropped tokens
+    /// let dropcheck = DropCheck::new();
+    /// let (t0, t1) = (dropcheck.token(), dropcheck.token());
     ///
-    /// let t1 = set.token();
-    /// assert!(set.none_dropped());
+    /// drop(t0);
+    /// dropcheck.retain_live();
+    /// assert!(dropcheck.none_dropped());
+    /// assert_eq!(t1.id(), 1);
+    /// ```
     ///
-    /// let t2 = set.token();
-    /// assert!(set.none_dropped());
+    /// Over-dropping a token whose state was removed is still reported:
     ///
-    /// drop(t1);
-    /// assert!(!set.none_dropped());
     /// ```
-    pub fn none_dropped(&self) -> bool {
-        self.set.read().unwrap()
-            .iter().all(|state| state.is_not_dropped())
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new().lenient();
+    /// let mut token = dropcheck.token();
+    ///
+    /// unsafe { std::ptr::drop_in_place(&mut token) };
+    /// dropcheck.retain_live();
+    /// unsafe { std::ptr::drop_in_place(&mut token) };
+    /// std::mem::forget(token);
+    ///
+    /// assert_eq!(dropcheck.status_counts().over_dropped(), 1);
+    /// assert!(dropcheck.check().is_err());
+    /// # dropcheck.disable_drop_assert();
+    /// ```
+    pub fn retain_live(&self) {
+        let mut retired = self.set.retired.lock().unwrap_or_else(PoisonError::into_inner);
+        self.set.states.write().unwrap_or_else(PoisonError::into_inner).retain(|state| {
+            if state.count.load(Ordering::SeqCst) == state.expected_drops() {
+                self.set.remove_counts(state);
+                state.retired.store(true, Ordering::SeqCst);
+                retired.push(Arc::clone(state));
+                false
+            } else {
+                true
+            }
+        })
     }
 
-    /// Returns true if all of the `Token`s have been dropped.
+    /// Checks the set like its destructor would, then resets it to its initial state, so that a
+    /// single set can be reused across the cases of a parameterized test.
+    ///
+    /// Every state is removed, and the counters behind token ids and drop order are reset, so ids
+    /// restart from zero in the next case. Ids therefore can't be used to correlate tokens across
+    /// cases.
+    ///
+    /// A token of a previous case that's dropped again after the reset is still reported, as an
+    /// over-dropped token of the current case. As with `retain_live`, the states of previous
+    /// cases stay allocated until the set is dropped, so that this can be detected.
+    ///
+    /// # Panics
+    ///
+    /// Panics with a `DropError` description if any token of the previous case was leaked or
+    /// dropped more than once. The set is left untouched in that case.
     ///
     /// # Examples
     ///
     /// ```
     /// # use dropcheck::DropCheck;
-    /// let set = DropCheck::new();
-    /// assert!(set.all_dropped()); // all of the tokens in an empty set have been dropped
+    /// let dropcheck = DropCheck::new();
     ///
-    /// let mut v = vec![];
-    /// for _ in 0 .. 100 {
-    ///     v.push(set.token());
+    /// for len in 1 .. 4 {
+    ///     let v: Vec<_> = (0 .. len).map(|_| dropcheck.token()).collect();
+    ///     assert_eq!(v[0].id(), 0);
+    ///     drop(v);
+    ///
+    ///     dropcheck.reset_for_next_case();
     /// }
-    /// assert!(!set.all_dropped());
+    /// ```
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new().lenient();
+    /// let mut token = dropcheck.token();
+    ///
+    /// unsafe { std::ptr::drop_in_place(&mut token) };
+    /// dropcheck.reset_for_next_case();
+    /// unsafe { std::ptr::drop_in_place(&mut token) };
+    /// std::mem::forget(token);
+    ///
+    /// assert_eq!(dropcheck.status_counts().over_dropped(), 1);
+    /// # dropcheck.disable_drop_assert();
+    /// ```
+    #[track_caller]
+    pub fn reset_for_next_case(&self) {
+        let mut states = self.set.states.write().unwrap_or_else(PoisonError::into_inner);
+        if let Err(err) = self.set.checked_report(&states).into_result() {
+            drop(states);
+            panic!("{}", err);
+        }
+        let previous: Vec<_> = states.drain(..).collect();
+        for state in &previous {
+            state.retired.store(true, Ordering::SeqCst);
+        }
+        self.set.violations.lock().unwrap_or_else(PoisonError::into_inner).clear();
+        self.set.live.store(0, Ordering::SeqCst);
+        self.set.dropped.store(0, Ordering::SeqCst);
+        self.set.over_dropped.store(0, Ordering::SeqCst);
+        self.set.next_id.store(0, Ordering::SeqCst);
+        self.set.next_drop_seq.store(0, Ordering::SeqCst);
+        #[cfg(feature = "metrics")]
+        self.set.publish_live();
+        drop(states);
+
+        self.set.retired.lock().unwrap_or_else(PoisonError::into_inner).extend(previous);
+    }
+
+    /// Returns a future that resolves once every token in this set has been dropped.
+    ///
+    /// Resolves immediately if that's already the case. Like `all_dropped`, intentionally leaked
+    /// tokens are ignored.
+    ///
+    /// ```
+    /// # use std::{future::Future, pin::pin, sync::Arc, task::{Context, Poll, Wake}, thread::{self, Thread}};
+    /// # struct ThreadWaker(Thread);
+    /// # impl Wake for ThreadWaker {
+    /// #     fn wake(self: Arc<Self>) { self.0.unpark() }
+    /// # }
+    /// # fn block_on<F: Future>(f: F) -> F::Output {
+    /// #     let waker = Arc::new(ThreadWaker(thread::current())).into();
+    /// #     let mut cx = Context::from_waker(&waker);
+    /// #     let mut f = pin!(f);
+    /// #     loop {
+    /// #         match f.as_mut().poll(&mut cx) {
+    /// #             Poll::Ready(output) => return output,
+    /// #             Poll::Pending => thread::park(),
+    /// #         }
+    /// #     }
+    /// # }
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    ///
+    /// let v = vec![dropcheck.token(); 10];
+    /// thread::spawn(move || drop(v));
+    ///
+    /// block_on(dropcheck.all_dropped_future());
+    /// assert!(dropcheck.all_dropped());
+    /// ```
+    #[cfg(feature = "async")]
+    pub fn all_dropped_future(&self) -> impl Future<Output = ()> + '_ {
+        future::poll_fn(move |cx| {
+            if self.all_dropped() {
+                return Poll::Ready(());
+            }
+
+            {
+                let mut wakers = self.set.wakers.lock().unwrap_or_else(PoisonError::into_inner);
+                if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                    wakers.push(cx.waker().clone());
+                }
+            }
+
+            // A token may have been dropped after the check above, but before the waker was
+            // registered.
+            if self.all_dropped() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+    }
+
+    /// Asserts that running `f` doesn't drop any token in this set more than once.
+    ///
+    /// Over-drops that happened before `f` was run are ignored. Unless the set is `lenient`, an
+    /// over-drop panics as soon as it happens, so this is mostly useful with lenient sets.
+    ///
+    /// # Panics
+    ///
+    /// Panics with every token over-dropped by `f`.
+    ///
+    /// # Examples
+    ///
+    /// ```should_panic
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new().lenient();
+    /// let mut token = std::mem::ManuallyDrop::new(dropcheck.token());
+    ///
+    /// dropcheck.assert_no_over_drops_during(|| unsafe {
+    ///     std::mem::ManuallyDrop::drop(&mut token);
+    ///     std::mem::ManuallyDrop::drop(&mut token);
+    /// });
+    /// // panics with "tokens dropped more than once: ..."
+    /// ```
+    ///
+    /// Tokens are told apart even if they share an id:
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// use std::mem::ManuallyDrop;
+    /// use std::panic::{self, AssertUnwindSafe};
+    ///
+    /// let dropcheck = DropCheck::new().lenient();
+    /// let mut first = ManuallyDrop::new(dropcheck.token_with_id(1));
+    /// let _zero = dropcheck.token();
+    /// let mut second = ManuallyDrop::new(dropcheck.token());
+    /// assert_eq!(first.id(), second.id());
+    ///
+    /// unsafe {
+    ///     ManuallyDrop::drop(&mut second);
+    ///     ManuallyDrop::drop(&mut second);
+    /// }
+    /// let result = panic::catch_unwind(AssertUnwindSafe(|| {
+    ///     dropcheck.assert_no_over_drops_during(|| unsafe {
+    ///         ManuallyDrop::drop(&mut first);
+    ///         ManuallyDrop::drop(&mut first);
+    ///     })
+    /// }));
+    /// assert!(result.is_err());
+    /// # dropcheck.disable_drop_assert();
+    /// ```
+    #[track_caller]
+    pub fn assert_no_over_drops_during<F>(&self, f: F)
+        where F: FnOnce()
+    {
+        // Ids aren't necessarily unique, so states are told apart by address instead. No address
+        // is reused while the set is alive, as the set keeps every state it created alive.
+        let before: BTreeMap<*const DropState, usize> =
+            self.set.states.read().unwrap_or_else(PoisonError::into_inner).iter()
+                .map(|state| (Arc::as_ptr(state), state.drop_count()))
+                .collect();
+        f();
+
+        let mut msg = String::new();
+        for state in self.set.states.read().unwrap_or_else(PoisonError::into_inner).iter() {
+            let count = state.drop_count();
+            let prev = before.get(&Arc::as_ptr(state)).copied().unwrap_or(0);
+            if count > state.expected_drops() && count > prev {
+                msg.push_str(&format!("\n    token #{} (dropped {} times, {} before)",
+                                      state.id(), count, prev));
+            }
+        }
+        if !msg.is_empty() {
+            panic!("{}tokens dropped more than once:{}", self.set.prefix(), msg);
+        }
+    }
+
+    /// Returns true if dropping any token in this set panicked unexpectedly.
+    ///
+    /// Every token drop runs inside `catch_unwind` to record whether it panicked, which costs
+    /// little unless a panic actually occurs. Panics from `panicking_token`s are expected, and
+    /// don't count; the ones that do are usually over-drops, generation mismatches, or panics
+    /// from dropping a token's payload.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    ///
+    /// let (stale, generation) = dropcheck.generational_token();
+    /// generation.advance();
+    /// assert!(std::panic::catch_unwind(move || drop(stale)).is_err());
+    ///
+    /// assert!(dropcheck.any_drop_panicked());
+    /// assert_eq!(dropcheck.panicked_drops(), vec![0]);
+    /// ```
+    pub fn any_drop_panicked(&self) -> bool {
+        !self.panicked_drops().is_empty()
+    }
+
+    /// Returns the ids of the tokens whose drop panicked unexpectedly.
+    ///
+    /// See `any_drop_panicked`.
+    pub fn panicked_drops(&self) -> Vec<u64> {
+        self.set.states.read().unwrap_or_else(PoisonError::into_inner).iter()
+            .filter(|state| {
+                state.drop_panicked.load(Ordering::SeqCst) && !state.panic_expected.load(Ordering::SeqCst)
+            })
+            .map(|state| state.id())
+            .collect()
+    }
+
+    /// Returns the ids of the dropped tokens in this set, in the order they were dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    ///
+    /// let mut v = vec![dropcheck.token(), dropcheck.token(), dropcheck.token()];
+    /// v.remove(1);
+    /// assert_eq!(dropcheck.drop_order(), vec![1]);
     ///
     /// drop(v);
-    /// assert!(set.all_dropped()); // vec has dropped every token in it
+    /// assert_eq!(dropcheck.drop_order(), vec![1, 0, 2]);
     /// ```
-    pub fn all_dropped(&self) -> bool {
-        self.set.read().unwrap()
-            .iter().all(|state| state.is_dropped())
+    pub fn drop_order(&self) -> Vec<u64> {
+        self.report().drop_order()
+    }
+
+    /// Asserts that the order in which tokens were dropped satisfies every constraint in
+    /// `pattern`.
+    ///
+    /// Useful when only a partial ordering matters.
+    ///
+    /// # Panics
+    ///
+    /// Panics with the first constraint that isn't satisfied.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::{DropCheck, OrderConstraint::*};
+    /// let dropcheck = DropCheck::new();
+    ///
+    /// let mut v = vec![dropcheck.token(), dropcheck.token(), dropcheck.token()];
+    /// v.remove(1);
+    /// drop(v);
+    ///
+    /// dropcheck.assert_drop_order_matches_pattern(&[First(1), Before(0, 2), Adjacent(0, 2), Last(2)]);
+    /// ```
+    ///
+    /// ```should_panic
+    /// # use dropcheck::{DropCheck, OrderConstraint::*};
+    /// let dropcheck = DropCheck::new();
+    ///
+    /// let (t0, t1) = (dropcheck.token(), dropcheck.token());
+    /// drop(t1);
+    /// drop(t0);
+    ///
+    /// dropcheck.assert_drop_order_matches_pattern(&[Before(0, 1)]); // panics
+    /// ```
+    #[track_caller]
+    pub fn assert_drop_order_matches_pattern(&self, pattern: &[OrderConstraint]) {
+        let drop_order = self.drop_order();
+        if let Some(violated) = pattern.iter().find(|constraint| !constraint.is_satisfied_by(&drop_order)) {
+            panic!("{}drop order {:?} violates constraint: {}",
+                   self.set.prefix(), drop_order, violated);
+        }
+    }
+
+    /// Asserts that the tokens dropped so far were dropped in the order given by `expected`,
+    /// returning how many of them matched.
+    ///
+    /// Tokens in `expected` that haven't been dropped yet aren't an error, so this can be called
+    /// repeatedly while a long operation progresses, with the same or a longer `expected`.
+    ///
+    /// # Panics
+    ///
+    /// Panics at the first position where the drop order differs from `expected`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// use std::collections::VecDeque;
+    ///
+    /// let dropcheck = DropCheck::new();
+    /// let mut queue: VecDeque<_> = (0 .. 4).map(|_| dropcheck.token()).collect();
+    ///
+    /// queue.pop_front();
+    /// assert_eq!(dropcheck.assert_drop_order_prefix(&[0, 1, 2, 3]), 1);
+    ///
+    /// queue.pop_front();
+    /// queue.pop_front();
+    /// assert_eq!(dropcheck.assert_drop_order_prefix(&[0, 1, 2, 3]), 3);
+    /// ```
+    #[track_caller]
+    pub fn assert_drop_order_prefix(&self, expected: &[u64]) -> usize {
+        let drop_order = self.drop_order();
+        for (i, (actual, expected)) in drop_order.iter().zip(expected).enumerate() {
+            if actual != expected {
+                panic!(concat!("{}drop order {:?} differs at position {}: ",
+                               "expected token #{}, got token #{}"),
+                       self.set.prefix(), drop_order, i, expected, actual);
+            }
+        }
+        cmp::min(drop_order.len(), expected.len())
+    }
+
+    /// Asserts that tokens in this set were dropped in the order they were created, as a FIFO
+    /// queue would: the dropped tokens must be the earliest created ones, oldest dropped first.
+    ///
+    /// # Panics
+    ///
+    /// Panics with the first token that was dropped out of order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// use std::collections::VecDeque;
+    ///
+    /// let dropcheck = DropCheck::new();
+    /// let mut queue: VecDeque<_> = (0 .. 10).map(|_| dropcheck.token()).collect();
+    ///
+    /// queue.pop_front();
+    /// queue.pop_front();
+    /// dropcheck.assert_fifo();
+    ///
+    /// queue.pop_back(); // the newest token isn't next in line
+    /// assert!(std::panic::catch_unwind(|| dropcheck.assert_fifo()).is_err());
+    /// ```
+    #[track_caller]
+    pub fn assert_fifo(&self) {
+        if let Err(msg) = self.check_creation_order(false) {
+            panic!("{}not dropped in FIFO order: {}", self.set.prefix(), msg);
+        }
+    }
+
+    /// Asserts that tokens in this set were dropped in the reverse of the order they were created,
+    /// as a LIFO stack would: the dropped tokens must be the latest created ones, newest dropped
+    /// first.
+    ///
+    /// # Panics
+    ///
+    /// Panics with the first token that was dropped out of order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    /// let mut stack: Vec<_> = (0 .. 10).map(|_| dropcheck.token()).collect();
+    ///
+    /// stack.pop();
+    /// stack.pop();
+    /// dropcheck.assert_lifo();
+    /// ```
+    #[track_caller]
+    pub fn assert_lifo(&self) {
+        if let Err(msg) = self.check_creation_order(true) {
+            panic!("{}not dropped in LIFO order: {}", self.set.prefix(), msg);
+        }
+    }
+
+    /// Checks that the dropped tokens are the first ones created, dropped in creation order; or
+    /// with `reverse`, the last ones created, dropped in reverse creation order.
+    fn check_creation_order(&self, reverse: bool) -> Result<(), String> {
+        let states = self.set.states.read().unwrap_or_else(PoisonError::into_inner);
+        let mut states: Vec<&Arc<DropState>> = states.iter().collect();
+        let relation = if reverse {
+            states.reverse();
+            "later"
+        } else {
+            "earlier"
+        };
+
+        let mut live: Option<&DropState> = None;
+        let mut prev: Option<(u64, &DropState)> = None;
+        for state in states {
+            match state.drop_seq() {
+                None => {
+                    live = live.or(Some(state));
+                },
+                Some(seq) => {
+                    if let Some(live) = live {
+                        return Err(format!("token #{} was dropped while token #{}, created {}, is live",
+                                           state.id(), live.id(), relation));
+                    }
+                    if let Some((prev_seq, prev)) = prev {
+                        if seq < prev_seq {
+                            return Err(format!("token #{} was dropped before token #{}, created {}",
+                                               state.id(), prev.id(), relation));
+                        }
+                    }
+                    prev = Some((seq, state));
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Shrinks a sequence of operations that leaks or over-drops a token to a minimal reproducer.
+    ///
+    /// `run` replays a sequence of operations against a fresh, `lenient` `DropCheck`. A sequence
+    /// fails if it leaves any token in the set undropped, or drops any token more than once. Other
+    /// panics in `run`, such as an out-of-bounds index in a sequence that makes no sense once
+    /// shrunk, don't count as failures. Using delta-debugging, `minimize` returns a subsequence of
+    /// `ops` that still fails, and that no longer fails if any single operation is removed from it.
+    ///
+    /// Note that every panic in a replay is still reported by the panic hook.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `ops` itself doesn't fail.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// #[derive(Clone, Debug, PartialEq)]
+    /// enum Op { Push, Pop, Forget }
+    ///
+    /// let ops = vec![Op::Push, Op::Push, Op::Pop, Op::Push, Op::Forget, Op::Pop, Op::Push];
+    ///
+    /// let minimal = DropCheck::minimize(&ops, |dropcheck, ops| {
+    ///     let mut v = vec![];
+    ///     for op in ops {
+    ///         match op {
+    ///             Op::Push => v.push(dropcheck.token()),
+    ///             Op::Pop => { v.pop(); },
+    ///             Op::Forget => if let Some(token) = v.pop() {
+    ///                 std::mem::forget(token); // leaked!
+    ///             },
+    ///         }
+    ///     }
+    /// });
+    /// assert_eq!(minimal, vec![Op::Push, Op::Forget]);
+    /// ```
+    ///
+    /// Shrinking never settles on a sequence that merely panics:
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// #[derive(Clone, Debug, PartialEq)]
+    /// enum Op { Push, Remove(usize), Forget }
+    ///
+    /// let ops = vec![Op::Push, Op::Push, Op::Push, Op::Remove(2), Op::Forget];
+    ///
+    /// let minimal = DropCheck::minimize(&ops, |dropcheck, ops| {
+    ///     let mut v = vec![];
+    ///     for op in ops {
+    ///         match op {
+    ///             Op::Push => v.push(dropcheck.token()),
+    ///             Op::Remove(i) => { v.remove(*i); },
+    ///             Op::Forget => if let Some(token) = v.pop() {
+    ///                 std::mem::forget(token); // leaked!
+    ///             },
+    ///         }
+    ///     }
+    /// });
+    /// assert_eq!(minimal, vec![Op::Push, Op::Forget]);
+    /// ```
+    pub fn minimize<T, F>(ops: &[T], run: F) -> Vec<T>
+        where T: Clone,
+              F: Fn(&DropCheck, &[T])
+    {
+        let fails = |ops: &[T]| {
+            let dropcheck = DropCheck::new().lenient();
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| run(&dropcheck, ops)));
+            let counts = dropcheck.status_counts();
+            dropcheck.disarmed.store(true, Ordering::SeqCst);
+            counts.live() > 0 || counts.over_dropped() > 0
+        };
+
+        assert!(fails(ops), "operations neither leak nor over-drop any tokens");
+
+        let mut ops = ops.to_vec();
+        let mut n = 2;
+        while ops.len() >= 2 {
+            let chunk_len = ops.len().div_ceil(n);
+
+            let subset = ops.chunks(chunk_len).find(|subset| fails(subset)).map(<[T]>::to_vec);
+            if let Some(subset) = subset {
+                ops = subset;
+                n = 2;
+                continue;
+            }
+
+            let complement = (0 .. ops.len()).step_by(chunk_len)
+                .map(|start| {
+                    let mut complement = ops[.. start].to_vec();
+                    complement.extend_from_slice(&ops[cmp::min(start + chunk_len, ops.len()) ..]);
+                    complement
+                })
+                .find(|complement| fails(complement));
+            if let Some(complement) = complement {
+                ops = complement;
+                n = cmp::max(n - 1, 2);
+                continue;
+            }
+
+            if n >= ops.len() {
+                break;
+            }
+            n = cmp::min(n * 2, ops.len());
+        }
+        ops
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg
+    } else {
+        "Box<dyn Any>"
     }
 }
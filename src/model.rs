@@ -0,0 +1,129 @@
+//! Model-based testing of containers against a reference.
+
+use std::sync::Arc;
+
+use super::fuzz::{Container, Op};
+use super::{DropCheck, DropState};
+
+/// Applies `ops` to `container`, and the same operations to `reference`, checking after every
+/// step that the two agree.
+///
+/// The reference holds the state of every token that should be in the container, in order. Each
+/// step checks that:
+///
+/// * `pop` and `remove` return the token the reference says they should,
+/// * every token removed from the container has been dropped,
+/// * every token still in the reference is alive, so the container didn't drop the wrong one,
+/// * the container has the same length as the reference.
+///
+/// Once `run` returns, the reference describes the tokens left in the container; the tokens
+/// aren't checked when they're dropped later on, but their states can be. The container and the
+/// reference can be passed to `run` again, to continue from there: tokens are matched to their
+/// states by identity rather than id, as each run numbers its tokens from zero.
+///
+/// # Panics
+///
+/// Panics with the failing step and operation if the container and the reference disagree.
+///
+/// # Examples
+///
+/// ```
+/// use dropcheck::fuzz::Op;
+///
+/// let mut v = Vec::new();
+/// let mut reference = Vec::new();
+/// dropcheck::model::run(&[Op::Push, Op::Push, Op::Insert(1), Op::Remove(0), Op::Pop],
+///                       &mut v, &mut reference);
+///
+/// assert_eq!(v.len(), 1);
+/// assert_eq!(v[0].id(), reference[0].id());
+/// ```
+///
+/// Continuing from an earlier run, whose tokens have the same ids as the new ones:
+///
+/// ```
+/// use dropcheck::fuzz::Op;
+///
+/// let mut v = Vec::new();
+/// let mut reference = Vec::new();
+/// dropcheck::model::run(&[Op::Push, Op::Push], &mut v, &mut reference);
+/// dropcheck::model::run(&[Op::Push, Op::Remove(0), Op::Pop, Op::Pop], &mut v, &mut reference);
+///
+/// assert!(v.is_empty());
+/// ```
+#[track_caller]
+pub fn run<C: Container>(ops: &[Op], container: &mut C, reference: &mut Vec<Arc<DropState>>) {
+    // The tokens left in the container outlive the set, so the reference checks them instead.
+    let dropcheck = DropCheck::new();
+    dropcheck.disable_drop_assert();
+
+    for (i, op) in ops.iter().enumerate() {
+        let fail = |msg: String| -> ! { panic!("step #{} ({}): {}", i, op, msg) };
+        let mut removed = None;
+
+        match *op {
+            Op::Push => {
+                let (token, state) = dropcheck.pair();
+                container.push(token);
+                reference.push(state);
+            },
+            Op::Pop => {
+                let token = container.pop();
+                let expected = reference.pop();
+                match (&token, &expected) {
+                    (Some(token), Some(expected)) if !Arc::ptr_eq(&token.state, expected) => {
+                        fail(format!("popped token #{}, expected token #{}",
+                                     token.id(), expected.id()))
+                    },
+                    (Some(_), None) | (None, Some(_)) => {
+                        fail(format!("popped {:?}, expected {:?}", token.map(|token| token.id()),
+                                     expected.map(|state| state.id())))
+                    },
+                    _ => {},
+                }
+                drop(token);
+                removed = expected;
+            },
+            Op::Insert(index) => {
+                let index = index % (reference.len() + 1);
+                let (token, state) = dropcheck.pair();
+                container.insert(index, token);
+                reference.insert(index, state);
+            },
+            Op::Remove(index) => {
+                if !reference.is_empty() {
+                    let index = index % reference.len();
+                    let token = container.remove(index);
+                    let expected = reference.remove(index);
+                    if !Arc::ptr_eq(&token.state, &expected) {
+                        fail(format!("removed token #{}, expected token #{}",
+                                     token.id(), expected.id()));
+                    }
+                    drop(token);
+                    removed = Some(expected);
+                }
+            },
+            Op::Clone => drop(container.clone()),
+            Op::Clear => {
+                container.clear();
+                for state in reference.drain(..) {
+                    if state.drop_count() != 1 {
+                        fail(format!("{} dropped {} times by clear", state, state.drop_count()));
+                    }
+                }
+            },
+        }
+
+        if let Some(state) = removed {
+            if state.drop_count() != 1 {
+                fail(format!("{} dropped {} times after being removed", state, state.drop_count()));
+            }
+        }
+        if let Some(state) = reference.iter().find(|state| state.drop_count() != 0) {
+            fail(format!("{} dropped while still in the container", state));
+        }
+        if container.len() != reference.len() {
+            fail(format!("container has length {}, expected {}", container.len(), reference.len()));
+        }
+    }
+}
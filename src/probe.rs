@@ -0,0 +1,83 @@
+//! Tokens with chosen auto traits, for checking how a container propagates them.
+
+use std::cell::Cell;
+use std::marker::{PhantomData, PhantomPinned};
+use std::ops::Deref;
+
+use super::DropToken;
+
+/// Marker for a `ProbeToken` that is `Send`, `Sync` and `Unpin`, like a plain `DropToken`.
+#[derive(Debug)]
+pub struct Auto;
+
+/// Marker for a `ProbeToken` that is neither `Send` nor `Sync`, like `Rc`.
+#[derive(Debug)]
+pub struct NotSend(PhantomData<*const ()>);
+
+/// Marker for a `ProbeToken` that is `Send`, but not `Sync`, like `Cell`.
+#[derive(Debug)]
+pub struct NotSync(PhantomData<Cell<()>>);
+
+/// Marker for a `ProbeToken` that isn't `Unpin`.
+#[derive(Debug)]
+pub struct NotUnpin(PhantomPinned);
+
+/// A `DropToken` whose auto traits are controlled by the marker type `M`.
+///
+/// Created by `DropCheck::probe_token`. The token is `Send`, `Sync` and `Unpin` exactly when `M`
+/// is, so instantiating a generic container with each marker checks both that the container
+/// propagates auto traits correctly, and that it drops its elements correctly, in one test.
+///
+/// # Examples
+///
+/// ```
+/// # use dropcheck::{Auto, DropCheck, ProbeToken};
+/// fn assert_send<T: Send>(_: &T) {}
+///
+/// let dropcheck = DropCheck::new();
+/// let v: Vec<ProbeToken<Auto>> = vec![dropcheck.probe_token(), dropcheck.probe_token()];
+/// assert_send(&v);
+/// ```
+///
+/// A container of tokens that aren't `Send` isn't `Send` either:
+///
+/// ```compile_fail
+/// # use dropcheck::{DropCheck, NotSend, ProbeToken};
+/// fn assert_send<T: Send>(_: &T) {}
+///
+/// let dropcheck = DropCheck::new();
+/// let v: Vec<ProbeToken<NotSend>> = vec![dropcheck.probe_token()];
+/// assert_send(&v);
+/// ```
+#[derive(Debug)]
+pub struct ProbeToken<M> {
+    token: DropToken,
+    marker: PhantomData<M>,
+}
+
+impl<M> ProbeToken<M> {
+    pub(crate) fn new(token: DropToken) -> Self {
+        Self { token, marker: PhantomData }
+    }
+
+    /// Converts the probe into a plain `DropToken`, without dropping it.
+    pub fn into_token(self) -> DropToken {
+        self.token
+    }
+}
+
+impl<M> Deref for ProbeToken<M> {
+    type Target = DropToken;
+
+    fn deref(&self) -> &DropToken {
+        &self.token
+    }
+}
+
+/// Cloning a probe clones its token, creating a fresh state in the same set.
+impl<M> Clone for ProbeToken<M> {
+    #[track_caller]
+    fn clone(&self) -> Self {
+        Self::new(self.token.clone())
+    }
+}
@@ -0,0 +1,135 @@
+//! Many named `DropCheck` sets, checked together.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError, atomic::{AtomicBool, Ordering}};
+use std::thread;
+
+use super::{DropCheck, DropError, DropReport};
+
+/// Manages named `DropCheck` sets for table-driven tests, and checks all of them at once.
+///
+/// Sets are created on demand by `set`, named after the test case so that every message about
+/// them says which case failed. They don't check themselves when they're dropped; instead, the
+/// registry checks every set when it's dropped, and panics with the problems of all of the
+/// failing sets together.
+///
+/// # Examples
+///
+/// ```
+/// # use dropcheck::DropCheckRegistry;
+/// let registry = DropCheckRegistry::new();
+///
+/// for len in [0, 1, 10] {
+///     let dropcheck = registry.set(&format!("len {}", len));
+///     let v: Vec<_> = (0 .. len).map(|_| dropcheck.token()).collect();
+///     drop(v);
+/// }
+///
+/// registry.finish().unwrap();
+/// ```
+#[derive(Debug, Default)]
+pub struct DropCheckRegistry {
+    sets: Mutex<BTreeMap<String, Arc<DropCheck>>>,
+    disarmed: AtomicBool,
+}
+
+impl DropCheckRegistry {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the set named `name`, creating it if it doesn't exist yet.
+    pub fn set(&self, name: &str) -> Arc<DropCheck> {
+        let mut sets = self.sets.lock().unwrap_or_else(PoisonError::into_inner);
+        let dropcheck = sets.entry(name.to_owned()).or_insert_with(|| {
+            let dropcheck = DropCheck::named(name);
+            dropcheck.disable_drop_assert();
+            Arc::new(dropcheck)
+        });
+        Arc::clone(dropcheck)
+    }
+
+    /// Returns a report of every set, by name.
+    pub fn reports(&self) -> BTreeMap<String, DropReport> {
+        self.sets.lock().unwrap_or_else(PoisonError::into_inner).iter()
+            .map(|(name, dropcheck)| (name.clone(), dropcheck.report()))
+            .collect()
+    }
+
+    /// Checks that no token in any set was leaked or dropped more than once, without panicking.
+    pub fn check(&self) -> Result<(), RegistryError> {
+        let errors: Vec<DropError> = self.sets.lock().unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .filter_map(|dropcheck| dropcheck.check().err())
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(RegistryError { errors })
+        }
+    }
+
+    /// Consumes the registry, checking it like its destructor would, but returning the error
+    /// instead of panicking.
+    pub fn finish(self) -> Result<(), RegistryError> {
+        self.disarmed.store(true, Ordering::SeqCst);
+        self.check()
+    }
+}
+
+impl Drop for DropCheckRegistry {
+    fn drop(&mut self) {
+        if self.disarmed.load(Ordering::SeqCst) || thread::panicking() {
+            return;
+        }
+        if let Err(err) = self.check() {
+            panic!("{}", err);
+        }
+    }
+}
+
+/// The problems found in the sets of a `DropCheckRegistry`.
+///
+/// Returned by `DropCheckRegistry::check` and `DropCheckRegistry::finish`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegistryError {
+    errors: Vec<DropError>,
+}
+
+impl RegistryError {
+    /// Returns the errors of the failing sets, in order of their names.
+    pub fn errors(&self) -> &[DropError] {
+        &self.errors
+    }
+}
+
+/// Lists the problems of each failing set, prefixed with its name.
+///
+/// ```
+/// # use dropcheck::DropCheckRegistry;
+/// let registry = DropCheckRegistry::new();
+/// std::mem::forget(registry.set("case 1").token());
+/// drop(registry.set("case 2").token());
+/// std::mem::forget(registry.set("case 3").token());
+///
+/// let msg = registry.finish().unwrap_err().to_string();
+/// let lines: Vec<&str> = msg.lines().collect();
+/// assert_eq!(lines[0], "case 1: not all tokens dropped:");
+/// assert_eq!(lines[2], "case 3: not all tokens dropped:");
+/// ```
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, err) in self.errors.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", err)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for RegistryError {}
@@ -0,0 +1,1022 @@
+//! Owned snapshots of the tokens in a `DropCheck` set.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::panic::Location;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::PoisonError;
+use std::sync::atomic::Ordering;
+#[cfg(feature = "timing")]
+use std::time::Duration;
+
+use super::DropState;
+
+/// A snapshot of a single token's state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenReport {
+    id: u64,
+    name: Option<String>,
+    location: &'static Location<'static>,
+    drop_count: usize,
+    expected_drops: usize,
+    move_count: usize,
+    drop_seq: Option<u64>,
+    leak_expected: bool,
+    panic_expected: bool,
+    drop_panicked: bool,
+    created_on: Option<String>,
+    dropped_on: Option<String>,
+    #[cfg(feature = "timing")]
+    lifetime: Option<Duration>,
+}
+
+impl TokenReport {
+    pub(crate) fn new(state: &DropState) -> Self {
+        Self {
+            id: state.id(),
+            name: state.name().map(str::to_owned),
+            location: state.location(),
+            drop_count: state.count.load(Ordering::SeqCst),
+            expected_drops: state.expected_drops(),
+            move_count: state.move_count(),
+            drop_seq: state.drop_seq(),
+            leak_expected: state.leak_expected.load(Ordering::SeqCst),
+            panic_expected: state.panic_expected.load(Ordering::SeqCst),
+            drop_panicked: state.drop_panicked.load(Ordering::SeqCst),
+            created_on: state.created_on.clone(),
+            dropped_on: state.dropped_on.lock().unwrap_or_else(PoisonError::into_inner).clone(),
+            #[cfg(feature = "timing")]
+            lifetime: state.lifetime(),
+        }
+    }
+
+    /// Returns the id of the token.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns the name of the token, if it has one.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns where the token was created.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+
+    /// Returns the number of times the token was dropped.
+    pub fn drop_count(&self) -> usize {
+        self.drop_count
+    }
+
+    /// Returns the number of times the token was expected to be dropped.
+    pub fn expected_drops(&self) -> usize {
+        self.expected_drops
+    }
+
+    /// Returns the number of times the token was marked as moved.
+    pub fn move_count(&self) -> usize {
+        self.move_count
+    }
+
+    /// Returns true if the token was dropped at least once.
+    pub fn is_dropped(&self) -> bool {
+        self.drop_count > 0
+    }
+
+    /// Returns true if the token wasn't dropped as many times as expected, usually once, and
+    /// wasn't leaked intentionally either.
+    ///
+    /// This can't tell a token that was forgotten, with `mem::forget` or a raw pointer that was
+    /// never freed, from one that's still owned somewhere: both keep their reference to the
+    /// state, so even the reference count of the state is the same.
+    pub fn is_leaked(&self) -> bool {
+        self.drop_count < self.expected_drops && !self.leak_expected
+    }
+
+    /// Returns true if the token was dropped more times than expected, usually once.
+    pub fn is_over_dropped(&self) -> bool {
+        self.drop_count > self.expected_drops
+    }
+
+    /// Returns true if the token was leaked with `DropToken::leak_intentionally`.
+    pub fn is_leak_expected(&self) -> bool {
+        self.leak_expected
+    }
+
+    /// Returns true if dropping the token panicked, and that wasn't expected.
+    pub fn drop_panicked_unexpectedly(&self) -> bool {
+        self.drop_panicked && !self.panic_expected
+    }
+
+    /// Returns the name of the thread that created the token, if thread naming was enabled.
+    pub fn created_on(&self) -> Option<&str> {
+        self.created_on.as_deref()
+    }
+
+    /// Returns the name of the thread that first dropped the token, if thread naming was enabled
+    /// and the token was dropped.
+    pub fn dropped_on(&self) -> Option<&str> {
+        self.dropped_on.as_deref()
+    }
+
+    /// Returns how long the token lived, if it was dropped.
+    #[cfg(feature = "timing")]
+    pub fn lifetime(&self) -> Option<Duration> {
+        self.lifetime
+    }
+
+    fn status(&self) -> String {
+        match self.drop_count {
+            0 if self.leak_expected => "leaked on purpose".to_owned(),
+            0 if self.move_count > 0 => format!("not dropped, moved {} times", self.move_count),
+            0 => "not dropped".to_owned(),
+            x if self.expected_drops != 1 => {
+                format!("dropped {} of {} times", x, self.expected_drops)
+            },
+            1 => "dropped".to_owned(),
+            x => format!("dropped {} times", x),
+        }
+    }
+}
+
+/// Describes the token, including its name if it has one, and the threads it was created and
+/// dropped on if thread naming was enabled.
+impl fmt::Display for TokenReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "token #{}", self.id)?;
+        if let Some(name) = &self.name {
+            write!(f, " ({})", name)?;
+        }
+        if let Some(created_on) = &self.created_on {
+            write!(f, " created on thread '{}'", created_on)?;
+            if let Some(dropped_on) = &self.dropped_on {
+                write!(f, ", dropped on thread '{}'", dropped_on)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The number of tokens in each lifecycle state, across a `DropCheck` set.
+///
+/// Created by `DropCheck::status_counts`. Every token is counted in exactly one state.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StatusCounts {
+    pub(crate) live: usize,
+    pub(crate) dropped: usize,
+    pub(crate) over_dropped: usize,
+    pub(crate) disarmed: usize,
+    pub(crate) expected_leak: usize,
+}
+
+impl StatusCounts {
+    /// Returns the number of tokens that haven't been dropped yet, and will be checked when the
+    /// set is dropped.
+    pub fn live(&self) -> usize {
+        self.live
+    }
+
+    /// Returns the number of tokens dropped exactly once.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Returns the number of tokens dropped more than once.
+    pub fn over_dropped(&self) -> usize {
+        self.over_dropped
+    }
+
+    /// Returns the number of tokens that haven't been dropped yet, in a set whose final check was
+    /// disabled with `DropCheck::disable_drop_assert`.
+    pub fn disarmed(&self) -> usize {
+        self.disarmed
+    }
+
+    /// Returns the number of tokens leaked with `DropToken::leak_intentionally`.
+    pub fn expected_leak(&self) -> usize {
+        self.expected_leak
+    }
+
+    /// Returns the total number of tokens.
+    pub fn total(&self) -> usize {
+        self.live + self.dropped + self.over_dropped + self.disarmed + self.expected_leak
+    }
+}
+
+impl fmt::Display for StatusCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} live, {} dropped, {} over-dropped, {} disarmed, {} leaked on purpose",
+               self.live, self.dropped, self.over_dropped, self.disarmed, self.expected_leak)
+    }
+}
+
+/// A snapshot of every token in a `DropCheck` set.
+///
+/// Created by `DropCheck::report` and `DropCheck::into_report`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DropReport {
+    tokens: Vec<TokenReport>,
+    thread_naming: bool,
+    name: Option<String>,
+    violations: Vec<String>,
+}
+
+impl DropReport {
+    pub(crate) fn new(tokens: Vec<TokenReport>, thread_naming: bool, name: Option<String>,
+                      violations: Vec<String>)
+        -> Self
+    {
+        Self { tokens, thread_naming, name, violations }
+    }
+
+    /// Returns the name of the set, if it has one.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns the reports of every token, in creation order.
+    pub fn tokens(&self) -> &[TokenReport] {
+        &self.tokens
+    }
+
+    /// Returns the distinct invariant violations recorded by watchdogs of the set, in the order
+    /// they were first seen, followed by the names of the invariants added with
+    /// `DropCheck::add_invariant` that failed, if this report came from a check.
+    pub fn violations(&self) -> &[String] {
+        &self.violations
+    }
+
+    pub(crate) fn add_violation(&mut self, violation: String) {
+        if !self.violations.contains(&violation) {
+            self.violations.push(violation);
+        }
+    }
+
+    /// Returns the reports of the leaked tokens.
+    pub fn leaked(&self) -> impl Iterator<Item = &TokenReport> {
+        self.tokens.iter().filter(|token| token.is_leaked())
+    }
+
+    /// Returns the reports of the tokens dropped more than once.
+    pub fn over_dropped(&self) -> impl Iterator<Item = &TokenReport> {
+        self.tokens.iter().filter(|token| token.is_over_dropped())
+    }
+
+    /// Returns the ids of the dropped tokens, in the order they were dropped.
+    pub fn drop_order(&self) -> Vec<u64> {
+        let mut dropped: Vec<(u64, u64)> = self.tokens.iter()
+            .filter_map(|token| token.drop_seq.map(|seq| (seq, token.id)))
+            .collect();
+        dropped.sort_unstable();
+        dropped.into_iter().map(|(_, id)| id).collect()
+    }
+
+    /// Returns how many tokens were created and dropped on each thread, by thread name.
+    ///
+    /// Lets a test check that a sharded or work-stealing container actually spreads destruction
+    /// across threads, or confines it to one. Thread names are only recorded if thread naming was
+    /// enabled for the set, so this is empty otherwise. Unnamed threads are all counted as
+    /// `<unnamed>`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// use std::thread;
+    ///
+    /// let dropcheck = DropCheck::new().with_thread_naming();
+    /// let v: Vec<_> = (0 .. 3).map(|_| dropcheck.token()).collect();
+    ///
+    /// thread::Builder::new().name("dropper".into())
+    ///     .spawn(move || drop(v))
+    ///     .unwrap().join().unwrap();
+    ///
+    /// let stats = dropcheck.report().thread_stats();
+    /// assert_eq!(stats["main"].created(), 3);
+    /// assert_eq!(stats["main"].dropped(), 0);
+    /// assert_eq!(stats["dropper"].dropped(), 3);
+    /// ```
+    pub fn thread_stats(&self) -> BTreeMap<String, ThreadStats> {
+        let mut stats: BTreeMap<String, ThreadStats> = BTreeMap::new();
+        for token in &self.tokens {
+            if let Some(created_on) = &token.created_on {
+                stats.entry(created_on.clone()).or_default().created += 1;
+            }
+            if let Some(dropped_on) = &token.dropped_on {
+                stats.entry(dropped_on.clone()).or_default().dropped += 1;
+            }
+        }
+        stats
+    }
+
+    /// Returns aggregate statistics on how long the dropped tokens lived.
+    ///
+    /// Returns `None` if no token has been dropped yet.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// use std::time::Duration;
+    ///
+    /// let dropcheck = DropCheck::new();
+    /// let v = vec![dropcheck.token(), dropcheck.token()];
+    /// std::thread::sleep(Duration::from_millis(10));
+    /// drop(v);
+    ///
+    /// let stats = dropcheck.report().lifetime_stats().unwrap();
+    /// assert_eq!(stats.count(), 2);
+    /// assert!(stats.min() >= Duration::from_millis(10));
+    /// assert!(stats.max() < Duration::from_secs(60)); // reclaimed promptly
+    /// ```
+    #[cfg(feature = "timing")]
+    pub fn lifetime_stats(&self) -> Option<LifetimeStats> {
+        let mut lifetimes = self.tokens.iter().filter_map(|token| token.lifetime);
+        let first = lifetimes.next()?;
+        let mut stats = LifetimeStats {
+            count: 0,
+            min: first,
+            max: first,
+            total: Duration::ZERO,
+            histogram: [0; LIFETIME_BUCKETS.len() + 1],
+        };
+        for lifetime in Some(first).into_iter().chain(lifetimes) {
+            stats.count += 1;
+            stats.min = stats.min.min(lifetime);
+            stats.max = stats.max.max(lifetime);
+            stats.total += lifetime;
+            let bucket = LIFETIME_BUCKETS.iter().position(|bound| lifetime < *bound)
+                .unwrap_or(LIFETIME_BUCKETS.len());
+            stats.histogram[bucket] += 1;
+        }
+        Some(stats)
+    }
+
+    /// Returns true if no token was leaked or dropped more than once, and no invariant was
+    /// violated.
+    pub fn is_clean(&self) -> bool {
+        self.is_clean_ignoring_violations() && self.violations.is_empty()
+    }
+
+    fn is_clean_ignoring_violations(&self) -> bool {
+        self.leaked().next().is_none() && self.over_dropped().next().is_none()
+    }
+
+    /// Returns the ids of the tokens that were leaked or dropped more than once.
+    fn failing(&self) -> BTreeSet<u64> {
+        self.leaked().chain(self.over_dropped()).map(|token| token.id).collect()
+    }
+
+    /// Compares the failing tokens in this report with the ones stored in the baseline file at
+    /// `path`, then replaces the baseline with this report.
+    ///
+    /// A missing baseline file is treated as a baseline without failures. The file format is
+    /// plain text, one failing token id per line.
+    pub fn compare_with_baseline(&self, path: &Path) -> io::Result<BaselineDiff> {
+        let baseline: BTreeSet<u64> = match fs::read_to_string(path) {
+            Ok(contents) => {
+                contents.lines().filter(|line| !line.is_empty()).map(|line| {
+                    line.trim().parse().map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+                }).collect::<io::Result<_>>()?
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => BTreeSet::new(),
+            Err(err) => return Err(err),
+        };
+
+        let failing = self.failing();
+        let contents: String = failing.iter().map(|id| format!("{}\n", id)).collect();
+        fs::write(path, contents)?;
+
+        Ok(BaselineDiff {
+            newly_failing: failing.difference(&baseline).copied().collect(),
+            newly_fixed: baseline.difference(&failing).copied().collect(),
+            still_failing: failing.intersection(&baseline).copied().collect(),
+        })
+    }
+
+    /// Returns the tokens whose status differs between this report and `other`, matched by id.
+    ///
+    /// A token's status is whether it was dropped, how many times, and whether it was leaked on
+    /// purpose. Tokens that are only in one of the reports are included too. Useful to compare two
+    /// checkpoints of the same set, or two runs with deterministic ids.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    /// let (t0, t1) = (dropcheck.token(), dropcheck.token());
+    /// let before = dropcheck.report();
+    ///
+    /// drop(t1);
+    /// let t2 = dropcheck.token();
+    /// let diff = before.diff(&dropcheck.report());
+    ///
+    /// assert_eq!(diff.to_string(), "token #1: not dropped -> dropped
+    /// token #2: absent -> not dropped
+    /// ");
+    /// # drop((t0, t2));
+    /// ```
+    pub fn diff(&self, other: &DropReport) -> ReportDiff {
+        fn by_id(report: &DropReport) -> BTreeMap<u64, &TokenReport> {
+            report.tokens.iter().map(|token| (token.id, token)).collect()
+        }
+        fn status(token: Option<&TokenReport>) -> Option<(usize, bool)> {
+            token.map(|token| (token.drop_count, token.leak_expected))
+        }
+
+        let (before, after) = (by_id(self), by_id(other));
+        let ids: BTreeSet<u64> = before.keys().chain(after.keys()).copied().collect();
+        let changes = ids.into_iter().filter_map(|id| {
+            let before = before.get(&id).copied();
+            let after = after.get(&id).copied();
+            if status(before) == status(after) {
+                None
+            } else {
+                Some(TokenChange { id, before: before.cloned(), after: after.cloned() })
+            }
+        }).collect();
+        ReportDiff { changes }
+    }
+
+    /// Returns the set of leaked tokens in this report, for comparison across runs.
+    pub fn snapshot(&self) -> DropSnapshot {
+        DropSnapshot { leaked: self.leaked().map(|token| token.id).collect() }
+    }
+
+    /// Returns the report in a stable text format, for snapshot tests.
+    ///
+    /// The format is one line per token, sorted by id:
+    ///
+    /// ```text
+    /// token #<id>[ (<name>)]: <status>[, order <n>]
+    /// ```
+    ///
+    /// The status is the same as in the summary table, like `dropped` or `not dropped`. Dropped
+    /// tokens also have their position in the drop order, starting from 1. Nothing that varies
+    /// between runs or refactors is included: no addresses, timings, thread names or source
+    /// locations. This format will only change in a semver-incompatible release.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    /// let mut v = vec![
+    ///     dropcheck.token(),
+    ///     dropcheck.build_token().name("middle").finish(),
+    ///     dropcheck.token(),
+    /// ];
+    /// v.swap_remove(0);
+    /// v.truncate(1);
+    ///
+    /// assert_eq!(dropcheck.report().to_stable_string(), "\
+    /// token #0: dropped, order 1
+    /// token #1 (middle): dropped, order 2
+    /// token #2: not dropped
+    /// ");
+    /// # drop(v);
+    /// ```
+    pub fn to_stable_string(&self) -> String {
+        let drop_order = self.drop_order();
+        let mut tokens: Vec<&TokenReport> = self.tokens.iter().collect();
+        tokens.sort_by_key(|token| token.id);
+
+        let mut s = String::new();
+        for token in tokens {
+            s.push_str(&format!("token #{}", token.id));
+            if let Some(name) = &token.name {
+                s.push_str(&format!(" ({})", name));
+            }
+            s.push_str(&format!(": {}", token.status()));
+            if token.drop_seq.is_some() {
+                if let Some(i) = drop_order.iter().position(|&id| id == token.id) {
+                    s.push_str(&format!(", order {}", i + 1));
+                }
+            }
+            s.push('\n');
+        }
+        s
+    }
+
+    /// Returns the report as a JSON object, for CI dashboards and other tooling.
+    ///
+    /// The object has the `name` of the set, a `clean` flag, and a `tokens` array with an object
+    /// per token in creation order. Names are `null` if unset, and so are thread names unless
+    /// thread naming was enabled.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    /// drop(dropcheck.token_with_named_value("head"));
+    ///
+    /// assert_eq!(dropcheck.report().to_json(), concat!(
+    ///     r#"{"name":null,"clean":true,"tokens":[{"id":0,"name":"\"head\"","status":"dropped","#,
+    ///     r#""drop_count":1,"move_count":0,"leak_expected":false,"drop_panicked":false,"#,
+    ///     r#""created_on":null,"dropped_on":null}]}"#));
+    /// ```
+    pub fn to_json(&self) -> String {
+        fn string(s: Option<&str>) -> String {
+            let s = match s {
+                Some(s) => s,
+                None => return "null".to_owned(),
+            };
+            let mut escaped = String::from("\"");
+            for c in s.chars() {
+                match c {
+                    '"' => escaped.push_str("\\\""),
+                    '\\' => escaped.push_str("\\\\"),
+                    '\n' => escaped.push_str("\\n"),
+                    c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+                    c => escaped.push(c),
+                }
+            }
+            escaped.push('"');
+            escaped
+        }
+
+        let tokens: Vec<String> = self.tokens.iter().map(|token| {
+            format!(concat!(r#"{{"id":{},"name":{},"status":{},"drop_count":{},"move_count":{},"#,
+                            r#""leak_expected":{},"drop_panicked":{},"created_on":{},"#,
+                            r#""dropped_on":{}}}"#),
+                    token.id, string(token.name()), string(Some(&token.status())),
+                    token.drop_count, token.move_count, token.leak_expected,
+                    token.drop_panicked_unexpectedly(), string(token.created_on()),
+                    string(token.dropped_on()))
+        }).collect();
+        format!(r#"{{"name":{},"clean":{},"tokens":[{}]}}"#,
+                string(self.name()), self.is_clean(), tokens.join(","))
+    }
+
+    /// Returns the report as a JUnit XML test suite, with a test case per token.
+    ///
+    /// The suite is named after the set, or `dropcheck` if the set has no name.
+    ///
+    /// Leaked tokens, tokens dropped more than once, and tokens whose drop panicked unexpectedly
+    /// are failed test cases; most CI systems can display the file directly.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use dropcheck::DropCheck;
+    /// let dropcheck = DropCheck::new();
+    /// let token = dropcheck.token();
+    /// std::mem::forget(token);
+    ///
+    /// assert_eq!(dropcheck.report().to_junit_xml(), r#"<?xml version="1.0" encoding="UTF-8"?>
+    /// <testsuite name="dropcheck" tests="1" failures="1">
+    ///   <testcase name="token #0"><failure message="not dropped"/></testcase>
+    /// </testsuite>
+    /// "#);
+    /// # dropcheck.disable_drop_assert();
+    /// ```
+    pub fn to_junit_xml(&self) -> String {
+        fn escape(s: &str) -> String {
+            s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+             .replace('"', "&quot;").replace('\'', "&apos;")
+        }
+
+        let failures = self.tokens.iter().filter(|token| {
+            token.is_leaked() || token.is_over_dropped() || token.drop_panicked_unexpectedly()
+        }).count();
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(&format!("<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\">\n",
+                              escape(self.name().unwrap_or("dropcheck")), self.tokens.len(),
+                              failures));
+        for token in &self.tokens {
+            let mut name = format!("token #{}", token.id);
+            if let Some(token_name) = &token.name {
+                name.push_str(&format!(" ({})", token_name));
+            }
+            let failure = if token.is_leaked() || token.is_over_dropped() {
+                Some(token.status())
+            } else if token.drop_panicked_unexpectedly() {
+                Some("drop panicked".to_owned())
+            } else {
+                None
+            };
+            match failure {
+                Some(failure) => {
+                    xml.push_str(&format!(concat!("  <testcase name=\"{}\">",
+                                                  "<failure message=\"{}\"/></testcase>\n"),
+                                          escape(&name), escape(&failure)));
+                },
+                None => xml.push_str(&format!("  <testcase name=\"{}\"/>\n", escape(&name))),
+            }
+        }
+        xml.push_str("</testsuite>\n");
+        xml
+    }
+
+    /// Returns `Ok` if the report is clean, or a `DropError` describing the problems otherwise.
+    pub fn into_result(self) -> Result<(), DropError> {
+        if self.is_clean() {
+            Ok(())
+        } else {
+            Err(DropError { report: self })
+        }
+    }
+}
+
+/// Formats a summary table of every token, headed by the name of the set if it has one.
+impl fmt::Display for DropReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(name) = &self.name {
+            writeln!(f, "{}:", name)?;
+        }
+        if self.thread_naming {
+            writeln!(f, "{:>8}  {:<18}  {:<18}  dropped on", "id", "status", "created on")?;
+        } else {
+            writeln!(f, "{:>8}  status", "id")?;
+        }
+
+        for token in &self.tokens {
+            match &token.created_on {
+                Some(created_on) => {
+                    writeln!(f, "{:>8}  {:<18}  {:<18}  {}", token.id, token.status(), created_on,
+                             token.dropped_on.as_deref().unwrap_or("-"))?;
+                },
+                None => writeln!(f, "{:>8}  {}", token.id, token.status())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The upper bounds of the buckets of a `LifetimeStats` histogram, except for the last bucket,
+/// which is unbounded.
+#[cfg(feature = "timing")]
+const LIFETIME_BUCKETS: [Duration; 8] = [
+    Duration::from_micros(1),
+    Duration::from_micros(10),
+    Duration::from_micros(100),
+    Duration::from_millis(1),
+    Duration::from_millis(10),
+    Duration::from_millis(100),
+    Duration::from_secs(1),
+    Duration::from_secs(10),
+];
+
+/// Aggregate statistics on how long the dropped tokens of a set lived.
+///
+/// Created by `DropReport::lifetime_stats`.
+#[cfg(feature = "timing")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LifetimeStats {
+    count: usize,
+    min: Duration,
+    max: Duration,
+    total: Duration,
+    histogram: [usize; LIFETIME_BUCKETS.len() + 1],
+}
+
+#[cfg(feature = "timing")]
+impl LifetimeStats {
+    /// Returns the number of dropped tokens the statistics cover.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Returns the shortest lifetime.
+    pub fn min(&self) -> Duration {
+        self.min
+    }
+
+    /// Returns the longest lifetime.
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// Returns the mean lifetime.
+    pub fn mean(&self) -> Duration {
+        self.total / self.count as u32
+    }
+
+    /// Returns a histogram of the lifetimes, in buckets growing tenfold from under a microsecond
+    /// to ten seconds and over.
+    ///
+    /// Each bucket is returned as its exclusive upper bound and the number of tokens in it. The
+    /// last bucket has no upper bound, and is returned with `None`.
+    pub fn histogram(&self) -> Vec<(Option<Duration>, usize)> {
+        LIFETIME_BUCKETS.iter().map(|bound| Some(*bound)).chain(Some(None))
+            .zip(self.histogram.iter().copied())
+            .collect()
+    }
+}
+
+#[cfg(feature = "timing")]
+impl fmt::Display for LifetimeStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} tokens lived min {:?}, mean {:?}, max {:?}",
+               self.count, self.min, self.mean(), self.max)
+    }
+}
+
+/// The number of tokens created and dropped on a single thread.
+///
+/// Created by `DropReport::thread_stats`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadStats {
+    created: usize,
+    dropped: usize,
+}
+
+impl ThreadStats {
+    /// Returns the number of tokens created on the thread.
+    pub fn created(&self) -> usize {
+        self.created
+    }
+
+    /// Returns the number of tokens first dropped on the thread.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+impl fmt::Display for ThreadStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} created, {} dropped", self.created, self.dropped)
+    }
+}
+
+/// The difference between the failing tokens of a run and those of a stored baseline.
+///
+/// Created by `DropCheck::compare_with_baseline`. A token is failing if it was leaked or dropped
+/// more than once. Tokens are matched by id, so the ids have to be deterministic across runs.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BaselineDiff {
+    newly_failing: Vec<u64>,
+    newly_fixed: Vec<u64>,
+    still_failing: Vec<u64>,
+}
+
+impl BaselineDiff {
+    /// Returns the ids of the tokens that are failing now, but weren't in the baseline.
+    pub fn newly_failing(&self) -> &[u64] {
+        &self.newly_failing
+    }
+
+    /// Returns the ids of the tokens that were failing in the baseline, but aren't now.
+    pub fn newly_fixed(&self) -> &[u64] {
+        &self.newly_fixed
+    }
+
+    /// Returns the ids of the tokens that are failing both now and in the baseline.
+    pub fn still_failing(&self) -> &[u64] {
+        &self.still_failing
+    }
+
+    /// Returns true if nothing changed since the baseline.
+    pub fn is_unchanged(&self) -> bool {
+        self.newly_failing.is_empty() && self.newly_fixed.is_empty()
+    }
+}
+
+impl fmt::Display for BaselineDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "newly failing: {:?}", self.newly_failing)?;
+        writeln!(f, "newly fixed: {:?}", self.newly_fixed)?;
+        writeln!(f, "still failing: {:?}", self.still_failing)
+    }
+}
+
+/// The tokens whose status differs between two reports.
+///
+/// Created by `DropReport::diff`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ReportDiff {
+    changes: Vec<TokenChange>,
+}
+
+impl ReportDiff {
+    /// Returns the changed tokens, in ascending order of id.
+    pub fn changes(&self) -> &[TokenChange] {
+        &self.changes
+    }
+
+    /// Returns true if no token's status changed.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/// Formats one line per changed token.
+impl fmt::Display for ReportDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+/// The status of a single token in two reports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenChange {
+    id: u64,
+    before: Option<TokenReport>,
+    after: Option<TokenReport>,
+}
+
+impl TokenChange {
+    /// Returns the id of the token.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns the token in the first report, if it was in it.
+    pub fn before(&self) -> Option<&TokenReport> {
+        self.before.as_ref()
+    }
+
+    /// Returns the token in the second report, if it was in it.
+    pub fn after(&self) -> Option<&TokenReport> {
+        self.after.as_ref()
+    }
+}
+
+impl fmt::Display for TokenChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = |token: &Option<TokenReport>| {
+            token.as_ref().map_or_else(|| "absent".to_owned(), TokenReport::status)
+        };
+        write!(f, "token #{}: {} -> {}", self.id, status(&self.before), status(&self.after))
+    }
+}
+
+/// The ids of the tokens leaked in a single run.
+///
+/// Created by `DropReport::snapshot`. Two snapshots are equal if the same tokens leaked, so
+/// identical runs can be deduplicated with a `HashSet`. Snapshots can be stored as run artifacts:
+/// the `Display` format is one token id per line, and is parsed back by `FromStr`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct DropSnapshot {
+    leaked: BTreeSet<u64>,
+}
+
+impl DropSnapshot {
+    /// Returns the ids of the leaked tokens, in ascending order.
+    pub fn leaked(&self) -> impl Iterator<Item = u64> + '_ {
+        self.leaked.iter().copied()
+    }
+}
+
+impl fmt::Display for DropSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for id in &self.leaked {
+            writeln!(f, "{}", id)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for DropSnapshot {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let leaked = s.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.trim().parse())
+            .collect::<Result<_, _>>()?;
+        Ok(Self { leaked })
+    }
+}
+
+/// Counts how often each token leaked across a number of runs.
+///
+/// Created by `triage`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct LeakFrequency {
+    runs: usize,
+    leaks: BTreeMap<u64, usize>,
+}
+
+impl LeakFrequency {
+    /// Returns the number of runs analyzed.
+    pub fn runs(&self) -> usize {
+        self.runs
+    }
+
+    /// Returns the number of runs in which the token with the given id leaked.
+    pub fn count(&self, id: u64) -> usize {
+        self.leaks.get(&id).copied().unwrap_or(0)
+    }
+
+    /// Returns the ids of the tokens that leaked in at least one run, with how many runs they
+    /// leaked in, in ascending order of id.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, usize)> + '_ {
+        self.leaks.iter().map(|(id, count)| (*id, *count))
+    }
+
+    /// Returns the ids of the tokens that leaked in some runs, but not all of them.
+    pub fn intermittent(&self) -> impl Iterator<Item = u64> + '_ {
+        self.iter().filter(move |(_, count)| *count < self.runs).map(|(id, _)| id)
+    }
+}
+
+/// Formats a table of the leaking tokens, with the number of runs each leaked in.
+impl fmt::Display for LeakFrequency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:>8}  leaked", "id")?;
+        for (id, count) in self.iter() {
+            writeln!(f, "{:>8}  {}/{}", id, count, self.runs)?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts how often each token leaked across the runs in `snapshots`.
+///
+/// Useful to triage flaky leaks: tokens that leak in every run point at a deterministic bug,
+/// whereas the ones that only leak sometimes point at a race. Tokens are matched by id, so the
+/// ids have to be deterministic across runs.
+///
+/// # Examples
+///
+/// ```
+/// # use dropcheck::DropCheck;
+/// let snapshots: Vec<_> = (0 .. 4).map(|run| {
+///     let dropcheck = DropCheck::new();
+///     let (t0, t1) = (dropcheck.token(), dropcheck.token());
+///     std::mem::forget(t0);
+///     if run % 2 == 0 {
+///         std::mem::forget(t1);
+///     } else {
+///         drop(t1);
+///     }
+///     dropcheck.into_report().snapshot()
+/// }).collect();
+///
+/// let frequency = dropcheck::triage(&snapshots);
+/// assert_eq!(frequency.count(0), 4);
+/// assert_eq!(frequency.count(1), 2);
+/// assert_eq!(frequency.intermittent().collect::<Vec<_>>(), vec![1]);
+/// ```
+pub fn triage(snapshots: &[DropSnapshot]) -> LeakFrequency {
+    let mut leaks = BTreeMap::new();
+    for id in snapshots.iter().flat_map(|snapshot| snapshot.leaked()) {
+        *leaks.entry(id).or_insert(0) += 1;
+    }
+    LeakFrequency { runs: snapshots.len(), leaks }
+}
+
+/// The tokens of a `DropCheck` set were leaked, or dropped more than once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DropError {
+    report: DropReport,
+}
+
+impl DropError {
+    /// Returns the report of the set that failed the check.
+    pub fn report(&self) -> &DropReport {
+        &self.report
+    }
+}
+
+impl fmt::Display for DropError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(name) = &self.report.name {
+            write!(f, "{}: ", name)?;
+        }
+        let mut leaked = self.report.leaked().peekable();
+        if leaked.peek().is_some() {
+            write!(f, "not all tokens dropped:")?;
+            for token in leaked {
+                write!(f, "\n    {}, created at {}:{}", token, token.location.file(),
+                       token.location.line())?;
+            }
+            if self.report.over_dropped().next().is_some() {
+                writeln!(f)?;
+            }
+        }
+
+        let mut over_dropped = self.report.over_dropped().peekable();
+        if over_dropped.peek().is_some() {
+            write!(f, "tokens dropped more than once:")?;
+            for token in over_dropped {
+                write!(f, "\n    {} (dropped {} times)", token, token.drop_count)?;
+            }
+        }
+
+        if !self.report.violations.is_empty() {
+            if !self.report.is_clean_ignoring_violations() {
+                writeln!(f)?;
+            }
+            write!(f, "invariant violated:")?;
+            for violation in &self.report.violations {
+                write!(f, "\n    {}", violation)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Error for DropError {}
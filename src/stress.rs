@@ -0,0 +1,71 @@
+//! Multi-threaded stress testing.
+
+use std::thread;
+
+use super::{panic_message, DropCheck};
+
+/// Runs `f` `iterations` times on each of `threads` threads, all sharing one `DropCheck` set,
+/// then checks the set.
+///
+/// The set is lenient and has thread naming enabled, so a token dropped twice on different
+/// threads doesn't panic in the middle of the run; it's reported at the end instead, along with
+/// the threads involved. Panics from `f` are caught per thread, and reported together with the
+/// set's own problems as a single failure.
+///
+/// # Panics
+///
+/// Panics if any thread panicked, or if any token was leaked or dropped more than once.
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::Mutex;
+///
+/// let shared = Mutex::new(Vec::new());
+/// dropcheck::stress(4, 100, |dropcheck| {
+///     shared.lock().unwrap().push(dropcheck.token());
+///     let token = shared.lock().unwrap().pop(); // possibly created on another thread
+///     drop(token);
+/// });
+/// ```
+///
+/// Since the set is checked when `stress` returns, tokens can't outlive it:
+///
+/// ```should_panic
+/// use std::sync::Mutex;
+///
+/// let shared = Mutex::new(Vec::new());
+/// dropcheck::stress(2, 10, |dropcheck| shared.lock().unwrap().push(dropcheck.token()));
+/// // panics, as the tokens are still in `shared`
+/// ```
+#[track_caller]
+pub fn stress<F>(threads: usize, iterations: usize, f: F)
+    where F: Fn(&DropCheck) + Sync
+{
+    let dropcheck = DropCheck::new().with_thread_naming().lenient();
+
+    let mut msg = thread::scope(|scope| {
+        let handles: Vec<_> = (0 .. threads).map(|_| {
+            scope.spawn(|| {
+                for _ in 0 .. iterations {
+                    f(&dropcheck);
+                }
+            })
+        }).collect();
+
+        let mut msg = String::new();
+        for (i, handle) in handles.into_iter().enumerate() {
+            if let Err(payload) = handle.join() {
+                msg.push_str(&format!("thread {} panicked: {}\n", i, panic_message(&*payload)));
+            }
+        }
+        msg
+    });
+
+    if let Err(err) = dropcheck.finish() {
+        msg.push_str(&err.to_string());
+    }
+    if !msg.is_empty() {
+        panic!("stress test failed:\n{}", msg.trim_end());
+    }
+}
@@ -0,0 +1,74 @@
+//! Drop-checking existing types, by embedding a tracker in them.
+
+use super::DropToken;
+
+/// A field that lets a value of an existing type be drop-checked.
+///
+/// A tracker starts out untracked, and does nothing. Once `DropCheck::track` has registered the
+/// value containing it, the tracker holds a token of that set, which is dropped along with the
+/// value; so leaking the value, or dropping it twice, is caught like it would be for a token.
+/// Cloning a tracked tracker registers the clone in the same set, as cloning a token does.
+#[derive(Debug, Clone, Default)]
+pub struct Tracker {
+    token: Option<DropToken>,
+}
+
+impl Tracker {
+    /// Creates an untracked tracker.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the id of the token the tracker holds, or `None` if it's untracked.
+    pub fn id(&self) -> Option<u64> {
+        self.token.as_ref().map(DropToken::id)
+    }
+
+    /// Returns true if the value has been registered with a set.
+    pub fn is_tracked(&self) -> bool {
+        self.token.is_some()
+    }
+
+    pub(crate) fn set(&mut self, token: DropToken) {
+        self.token = Some(token);
+    }
+}
+
+/// A type with an embedded `Tracker`, which `DropCheck::track` can register.
+///
+/// # Examples
+///
+/// ```
+/// use dropcheck::{DropCheck, TrackDrop, Tracker};
+///
+/// #[derive(Default)]
+/// struct Connection {
+///     open: bool,
+///     tracker: Tracker,
+/// }
+///
+/// impl Drop for Connection {
+///     fn drop(&mut self) {
+///         self.open = false;
+///     }
+/// }
+///
+/// impl TrackDrop for Connection {
+///     fn tracker(&mut self) -> &mut Tracker {
+///         &mut self.tracker
+///     }
+/// }
+///
+/// let dropcheck = DropCheck::new();
+/// let mut pool: Vec<Connection> = (0 .. 3).map(|_| Connection::default()).collect();
+/// for connection in &mut pool {
+///     dropcheck.track(connection);
+/// }
+///
+/// drop(pool);
+/// assert!(dropcheck.all_dropped());
+/// ```
+pub trait TrackDrop {
+    /// Returns the value's tracker.
+    fn tracker(&mut self) -> &mut Tracker;
+}
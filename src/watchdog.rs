@@ -0,0 +1,67 @@
+//! Checking an invariant of a `DropCheck` set periodically, from a background thread.
+
+use std::panic;
+use std::sync::{Arc, Condvar, Mutex, PoisonError, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use super::{DropReport, Set};
+
+/// A background thread checking an invariant of a `DropCheck` set, created by
+/// `DropCheck::watchdog`.
+///
+/// The thread stops when the watchdog is dropped, or once the set is gone.
+#[derive(Debug)]
+#[must_use = "the watchdog stops when it's dropped"]
+pub struct Watchdog {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    pub(crate) fn spawn<F>(set: Weak<Set>, interval: Duration, invariant: F) -> Self
+        where F: Fn(&DropReport) -> Result<(), String> + Send + 'static
+    {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let handle = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                let (stopped, condvar) = &*stop;
+                let mut stopped = stopped.lock().unwrap_or_else(PoisonError::into_inner);
+                while !*stopped {
+                    stopped = condvar.wait_timeout(stopped, interval)
+                        .unwrap_or_else(PoisonError::into_inner).0;
+                    if *stopped {
+                        break;
+                    }
+                    let set = match set.upgrade() {
+                        Some(set) => set,
+                        None => return,
+                    };
+                    let report = set.report(&set.states.read()
+                                                   .unwrap_or_else(PoisonError::into_inner));
+                    if let Err(violation) = invariant(&report) {
+                        set.record_violation(violation);
+                    }
+                }
+            })
+        };
+        Self { stop, handle: Some(handle) }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        let (stopped, condvar) = &*self.stop;
+        *stopped.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        condvar.notify_one();
+        if let Some(handle) = self.handle.take() {
+            if let Err(payload) = handle.join() {
+                // The invariant panicked. Panicking again while unwinding would abort.
+                if !thread::panicking() {
+                    panic::resume_unwind(payload);
+                }
+            }
+        }
+    }
+}